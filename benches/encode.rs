@@ -0,0 +1,101 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use network_tables::v4::{
+    encode::{write_value_message, BufferPool},
+    NtValue, Type,
+};
+
+fn scalars(c: &mut Criterion) {
+    let mut buf = Vec::with_capacity(64);
+
+    c.bench_function("f64", |b| {
+        b.iter(|| {
+            buf.clear();
+            write_value_message(&mut buf, 1, 1_000_000, &Type::Double, black_box(1.5)).unwrap();
+        })
+    });
+    c.bench_function("f64 to float topic", |b| {
+        b.iter(|| {
+            buf.clear();
+            write_value_message(&mut buf, 1, 1_000_000, &Type::Float, black_box(1.5)).unwrap();
+        })
+    });
+    c.bench_function("NtValue::Double", |b| {
+        let value = NtValue::Double(1.5);
+        b.iter(|| {
+            buf.clear();
+            write_value_message(&mut buf, 1, 1_000_000, &Type::Double, black_box(&value)).unwrap();
+        })
+    });
+}
+
+fn slices(c: &mut Criterion) {
+    let doubles: Vec<f64> = (0..64).map(|i| i as f64 * 0.5).collect();
+    let ints: Vec<i64> = (0..64).collect();
+    let strings: Vec<&str> = vec!["left", "center", "right"];
+    let mut buf = Vec::with_capacity(1024);
+
+    c.bench_function("f64 slice", |b| {
+        b.iter(|| {
+            buf.clear();
+            write_value_message(
+                &mut buf,
+                1,
+                1_000_000,
+                &Type::DoubleArray,
+                black_box(&doubles[..]),
+            )
+            .unwrap();
+        })
+    });
+    c.bench_function("i64 slice to double array topic", |b| {
+        b.iter(|| {
+            buf.clear();
+            write_value_message(
+                &mut buf,
+                1,
+                1_000_000,
+                &Type::DoubleArray,
+                black_box(&ints[..]),
+            )
+            .unwrap();
+        })
+    });
+    c.bench_function("str slice", |b| {
+        b.iter(|| {
+            buf.clear();
+            write_value_message(
+                &mut buf,
+                1,
+                1_000_000,
+                &Type::StringArray,
+                black_box(&strings[..]),
+            )
+            .unwrap();
+        })
+    });
+}
+
+fn raw(c: &mut Criterion) {
+    let payload = vec![0xAB_u8; 1024];
+    let mut buf = Vec::with_capacity(2048);
+
+    c.bench_function("raw 1KiB", |b| {
+        b.iter(|| {
+            buf.clear();
+            write_value_message(&mut buf, 1, 1_000_000, &Type::Raw, black_box(&payload[..]))
+                .unwrap();
+        })
+    });
+    c.bench_function("raw 1KiB from pool", |b| {
+        let pool = BufferPool::new();
+        b.iter(|| {
+            let mut buf = pool.take();
+            write_value_message(&mut buf, 1, 1_000_000, &Type::Raw, black_box(&payload[..]))
+                .unwrap();
+            pool.recycle(buf);
+        })
+    });
+}
+
+criterion_group!(benches, scalars, slices, raw);
+criterion_main!(benches);
@@ -13,6 +13,7 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
         network_tables::v4::client_config::Config {
             ..Default::default()
         },
+        None,
     )
     .await?;
     let published_topic = client
@@ -32,10 +33,10 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let task_client = client.clone();
     tokio::spawn(async move {
-        let mut counter = 0;
+        let mut counter: i64 = 0;
         loop {
             task_client
-                .publish_value(&published_topic, &network_tables::Value::from(counter))
+                .publish_value(&published_topic, &network_tables::NtValue::from(counter))
                 .await
                 .unwrap();
             counter += 1;
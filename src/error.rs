@@ -4,10 +4,16 @@ use thiserror::Error;
 pub enum Error {
     #[cfg(feature = "__v4")]
     #[error("WebSocket error: {0:?}")]
-    Tungstenite(#[from] tokio_tungstenite::tungstenite::Error),
+    Tungstenite(Box<tokio_tungstenite::tungstenite::Error>),
     #[cfg(feature = "__v4")]
     #[error("Json error: {0:?}")]
     SerdeJson(#[from] serde_json::Error),
+    #[cfg(feature = "__v4")]
+    #[error("Msgpack encode error: {0:?}")]
+    Encode(#[from] rmp::encode::ValueWriteError),
+    #[cfg(feature = "__v4")]
+    #[error("Msgpack decode error: {0:?}")]
+    Decode(#[from] rmp_serde::decode::Error),
     #[error("Io error: {0:?}")]
     Io(#[from] std::io::Error),
 
@@ -20,7 +26,37 @@ pub enum Error {
 
     #[error("Timed out connecting to server")]
     ConnectTimeout(#[from] tokio::time::error::Elapsed),
+    #[error("Not connected to the server")]
+    NotConnected,
+    #[error("Connection to the server is closed")]
+    Closed,
+    #[error("Too many messages are waiting to be sent to the server")]
+    QueueFull,
+    #[error("Timed out waiting")]
+    Timeout,
+    #[error("Connection to the server failed: {0}")]
+    ConnectionFailed(std::sync::Arc<Error>),
     // Server error
     #[error("Server responded with an invalid type of message")]
     InvalidMessageType(&'static str),
+    #[cfg(feature = "__v4")]
+    #[error("Expected a value of type {expected:?}, found {found}")]
+    TypeMismatch {
+        expected: crate::v4::Type,
+        found: &'static str,
+    },
+    #[cfg(feature = "__v4")]
+    #[error("Topic {topic} was published as {published:?}, but the server announced it as {announced:?}")]
+    TypeConflict {
+        topic: String,
+        published: crate::v4::Type,
+        announced: crate::v4::Type,
+    },
+}
+
+#[cfg(feature = "__v4")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::Tungstenite(Box::new(err))
+    }
 }
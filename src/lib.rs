@@ -11,6 +11,8 @@ pub mod v3;
 pub mod v4;
 #[cfg(feature = "__v4")]
 pub use rmpv::{self, Value};
+#[cfg(feature = "__v4")]
+pub use v4::NtValue;
 
 #[inline(always)]
 fn log_result<T, E: std::error::Error>(result: Result<T, E>) -> Result<T, E> {
@@ -0,0 +1,376 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use futures_util::{
+    future,
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+
+use super::{time_sync::time_diff, Client, MessageData, NtValue, PublishedTopic, Type};
+
+/// Stream of values returned by the adapters of [`MessageStreamExt`]
+pub type MessageStream = BoxStream<'static, Arc<MessageData>>;
+
+/// Adapters for streams of values such as [`Subscription`](super::Subscription).
+///
+/// Derived values keep the topic name & timestamp of the value they came from, their type matches the new value.
+pub trait MessageStreamExt: Stream<Item = Arc<MessageData>> + Sized + Send + 'static {
+    /// Replaces every value
+    fn map_value<F>(self, mut f: F) -> MessageStream
+    where
+        F: FnMut(&NtValue) -> NtValue + Send + 'static,
+    {
+        self.map(move |message| with_data(&message, f(&message.data)))
+            .boxed()
+    }
+
+    /// Only keeps values the predicate returns true for
+    fn filter_value<F>(self, mut f: F) -> MessageStream
+    where
+        F: FnMut(&NtValue) -> bool + Send + 'static,
+    {
+        self.filter(move |message| future::ready(f(&message.data)))
+            .boxed()
+    }
+
+    /// Replaces values, skipping the ones `f` returns `None` for
+    fn filter_map_value<F>(self, mut f: F) -> MessageStream
+    where
+        F: FnMut(&NtValue) -> Option<NtValue> + Send + 'static,
+    {
+        self.filter_map(move |message| {
+            future::ready(f(&message.data).map(|data| with_data(&message, data)))
+        })
+        .boxed()
+    }
+
+    /// Skips values equal to the previous value
+    fn dedup(self) -> MessageStream {
+        let mut previous: Option<NtValue> = None;
+        self.filter(move |message| {
+            let changed = previous.as_ref() != Some(&message.data);
+            if changed {
+                previous = Some(message.data.clone());
+            }
+            future::ready(changed)
+        })
+        .boxed()
+    }
+
+    /// Passes at most one value per `period`, the first one, and drops the rest
+    fn throttle(self, period: Duration) -> MessageStream {
+        let mut last: Option<tokio::time::Instant> = None;
+        self.filter(move |_| {
+            let now = tokio::time::Instant::now();
+            let pass = match last {
+                Some(last) => now.duration_since(last) >= period,
+                None => true,
+            };
+            if pass {
+                last = Some(now);
+            }
+            future::ready(pass)
+        })
+        .boxed()
+    }
+
+    /// Passes a value only once no new value was received for `period`
+    fn debounce(self, period: Duration) -> MessageStream {
+        stream::unfold(
+            (self.boxed(), None::<Arc<MessageData>>),
+            move |(mut stream, mut pending)| async move {
+                loop {
+                    match pending.take() {
+                        Some(message) => match tokio::time::timeout(period, stream.next()).await {
+                            Ok(Some(newer)) => pending = Some(newer),
+                            // Pass the last value before ending
+                            Ok(None) | Err(_) => return Some((message, (stream, None))),
+                        },
+                        None => pending = Some(stream.next().await?),
+                    }
+                }
+            },
+        )
+        .boxed()
+    }
+
+    /// Mean of the last `window` numeric values as a double, non numeric values are skipped
+    fn moving_average(self, window: usize) -> MessageStream {
+        let window = window.max(1);
+        let mut values = VecDeque::with_capacity(window);
+        let mut sum = 0.0;
+        self.filter_map(move |message| {
+            let value = message.data.as_f64();
+            future::ready(value.map(|value| {
+                if values.len() == window {
+                    sum -= values.pop_front().unwrap_or(0.0);
+                }
+                values.push_back(value);
+                sum += value;
+                with_data(&message, NtValue::Double(sum / values.len() as f64))
+            }))
+        })
+        .boxed()
+    }
+
+    /// Combines the newest values of both streams whenever either updates, once both have a value.
+    /// The result has the topic name of this stream and the newer timestamp of the two.
+    fn combine_latest<S, F>(self, other: S, mut f: F) -> MessageStream
+    where
+        S: Stream<Item = Arc<MessageData>> + Send + 'static,
+        F: FnMut(&NtValue, &NtValue) -> Option<NtValue> + Send + 'static,
+    {
+        let mut latest: (Option<Arc<MessageData>>, Option<Arc<MessageData>>) = (None, None);
+        stream::select(
+            self.map(|message| (true, message)),
+            other.map(|message| (false, message)),
+        )
+        .filter_map(move |(is_self, message)| {
+            if is_self {
+                latest.0 = Some(message);
+            } else {
+                latest.1 = Some(message);
+            }
+
+            let combined = match &latest {
+                (Some(a), Some(b)) => f(&a.data, &b.data).map(|data| {
+                    let timestamp = if time_diff(b.timestamp, a.timestamp) > 0 {
+                        b.timestamp
+                    } else {
+                        a.timestamp
+                    };
+                    Arc::new(MessageData {
+                        topic_name: a.topic_name.clone(),
+                        timestamp,
+                        r#type: derived_type(a, &data),
+                        data,
+                    })
+                }),
+                _ => None,
+            };
+            future::ready(combined)
+        })
+        .boxed()
+    }
+
+    /// Publishes every value to `topic` with the timestamp it was received with.
+    ///
+    /// Returns when the stream ends or publishing fails.
+    fn pipe_to(
+        self,
+        client: Client,
+        topic: PublishedTopic,
+    ) -> future::BoxFuture<'static, Result<(), crate::Error>> {
+        pipe(self, client, topic, true)
+    }
+
+    /// Like [`Self::pipe_to`], but publishes with the current server time instead
+    fn pipe_to_now(
+        self,
+        client: Client,
+        topic: PublishedTopic,
+    ) -> future::BoxFuture<'static, Result<(), crate::Error>> {
+        pipe(self, client, topic, false)
+    }
+}
+
+impl<S> MessageStreamExt for S where S: Stream<Item = Arc<MessageData>> + Send + 'static {}
+
+fn with_data(message: &MessageData, data: NtValue) -> Arc<MessageData> {
+    Arc::new(MessageData {
+        topic_name: message.topic_name.clone(),
+        timestamp: message.timestamp,
+        r#type: derived_type(message, &data),
+        data,
+    })
+}
+
+/// Type of a value derived from `message`, which is the message's type if the value is still sent as it
+/// (e.g. a string of a `json` topic) and otherwise the plain type of the value
+fn derived_type(message: &MessageData, data: &NtValue) -> Type {
+    let r#type = match data {
+        NtValue::Boolean(_) => Type::Boolean,
+        NtValue::Double(_) => Type::Double,
+        NtValue::Int(_) => Type::Int,
+        NtValue::Float(_) => Type::Float,
+        NtValue::String(_) => Type::String,
+        NtValue::Raw(_) => Type::Raw,
+        NtValue::BooleanArray(_) => Type::BooleanArray,
+        NtValue::DoubleArray(_) => Type::DoubleArray,
+        NtValue::IntArray(_) => Type::IntArray,
+        NtValue::FloatArray(_) => Type::FloatArray,
+        NtValue::StringArray(_) => Type::StringArray,
+    };
+
+    if r#type.as_u8() == message.r#type.as_u8() {
+        message.r#type.clone()
+    } else {
+        r#type
+    }
+}
+
+fn pipe<S>(
+    stream: S,
+    client: Client,
+    topic: PublishedTopic,
+    keep_timestamp: bool,
+) -> future::BoxFuture<'static, Result<(), crate::Error>>
+where
+    S: Stream<Item = Arc<MessageData>> + Send + 'static,
+{
+    Box::pin(async move {
+        let mut stream = stream.boxed();
+        while let Some(message) = stream.next().await {
+            let timestamp = if keep_timestamp {
+                message.timestamp
+            } else {
+                client.server_time()
+            };
+            client
+                .publish_value_w_timestamp(&topic, timestamp, &message.data)
+                .await?;
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+
+    fn message(r#type: Type, data: NtValue) -> Arc<MessageData> {
+        MessageData::received("/test", 0, r#type, data)
+    }
+
+    fn int(topic_name: &str, timestamp: u32, value: i64) -> Arc<MessageData> {
+        MessageData::received(topic_name, timestamp, Type::Int, NtValue::Int(value))
+    }
+
+    /// Ints which arrive the given number of milliseconds after the previous one
+    fn timed(values: &[(u64, i64)]) -> impl Stream<Item = Arc<MessageData>> + Send + 'static {
+        stream::iter(values.to_vec()).then(|(delay, value)| async move {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            int("/test", 0, value)
+        })
+    }
+
+    async fn values(stream: MessageStream) -> Vec<i64> {
+        stream
+            .filter_map(|message| future::ready(message.data.as_i64()))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn dedup() {
+        let stream = stream::iter([1, 1, 2, 2, 2, 1, 3].map(|value| int("/test", 0, value)));
+        assert_eq!(values(stream.dedup()).await, [1, 2, 1, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttle() {
+        let stream = timed(&[(0, 1), (10, 2), (30, 3), (60, 4), (40, 5)]);
+        // 1 at 0ms, 2 at 10ms, 3 at 40ms, 4 at 100ms, 5 at 140ms
+        assert_eq!(
+            values(stream.throttle(Duration::from_millis(50))).await,
+            [1, 4]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn debounce() {
+        let stream = timed(&[(0, 1), (10, 2), (10, 3), (100, 4), (10, 5), (100, 6)]);
+        let start = tokio::time::Instant::now();
+        let mut debounced = stream.debounce(Duration::from_millis(50));
+
+        assert_eq!(debounced.next().await.unwrap().data, NtValue::Int(3));
+        assert_eq!(start.elapsed(), Duration::from_millis(70));
+        assert_eq!(debounced.next().await.unwrap().data, NtValue::Int(5));
+        assert_eq!(start.elapsed(), Duration::from_millis(180));
+        // The last value is passed when the stream ends
+        assert_eq!(debounced.next().await.unwrap().data, NtValue::Int(6));
+        assert!(debounced.next().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn combine_latest() {
+        let a = timed(&[(0, 1), (20, 2)]);
+        let b = timed(&[(10, 10), (20, 20)]);
+        // Odd sums are skipped
+        let combined = a.combine_latest(b, |a, b| {
+            let sum = a.as_i64()? + b.as_i64()?;
+            (sum % 2 == 0).then_some(NtValue::Int(sum))
+        });
+        // a=1 b=10 at 10ms, a=2 b=10 at 20ms, a=2 b=20 at 30ms
+        assert_eq!(values(combined).await, [12, 22]);
+    }
+
+    #[tokio::test]
+    async fn combine_latest_takes_newer_timestamp_across_wrap_around() {
+        let combine = |a: Arc<MessageData>, b: Arc<MessageData>| async move {
+            stream::iter([a])
+                .combine_latest(stream::iter([b]), |a, _| Some(a.clone()))
+                .collect::<Vec<_>>()
+                .await
+        };
+
+        let combined = combine(int("/a", u32::MAX - 10, 1), int("/b", 5, 2)).await;
+        assert_eq!(combined.len(), 1);
+        assert_eq!(combined[0].timestamp, 5);
+        assert_eq!(combined[0].topic_name, "/a");
+
+        let combined = combine(int("/a", 5, 1), int("/b", u32::MAX - 10, 2)).await;
+        assert_eq!(combined[0].timestamp, 5);
+    }
+
+    #[tokio::test]
+    async fn pipe_to_keeps_timestamp() {
+        let (client, mut sent) = Client::unconnected();
+        let topic = client
+            .publish_topic("/out", Type::Double, None)
+            .await
+            .unwrap();
+        assert!(matches!(sent.recv().await, Some(Message::Text(_))));
+
+        let stream = stream::iter([int("/in", 1234, 3), int("/in", u32::MAX, 4)]);
+        stream.pipe_to(client, topic.clone()).await.unwrap();
+
+        for (timestamp, value) in [(1234u32, 3.0), (u32::MAX, 4.0)] {
+            let buf = match sent.recv().await {
+                Some(Message::Binary(buf)) => buf,
+                message => panic!("expected a value, got {message:?}"),
+            };
+            let message = rmpv::decode::read_value(&mut buf.as_slice()).unwrap();
+            assert_eq!(
+                message,
+                rmpv::Value::from(vec![
+                    rmpv::Value::from(topic.pubuid),
+                    rmpv::Value::from(timestamp),
+                    rmpv::Value::from(1),
+                    rmpv::Value::F64(value),
+                ])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn derived_values_have_matching_type() {
+        let values = stream::iter(vec![
+            message(Type::Int, NtValue::Int(1)),
+            message(Type::Int, NtValue::Int(2)),
+        ]);
+        let averages: Vec<_> = values.moving_average(2).collect().await;
+        assert_eq!(averages[1].data, NtValue::Double(1.5));
+        assert_eq!(averages[1].r#type, Type::Double);
+
+        let values = stream::iter(vec![message(Type::Json, NtValue::String("{}".to_owned()))]);
+        let mapped: Vec<_> = values
+            .map_value(|_| NtValue::String("[]".to_owned()))
+            .collect()
+            .await;
+        assert_eq!(mapped[0].r#type, Type::Json);
+    }
+}
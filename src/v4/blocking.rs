@@ -0,0 +1,320 @@
+//! Client for code which doesn't use async, such as scripts and plotting programs.
+//!
+//! The async client runs on a runtime owned by the blocking client, on its own thread.
+//! None of these methods can be called from inside an async runtime.
+
+use std::{net::SocketAddr, sync::Arc, thread::JoinHandle, time::Duration};
+
+use tokio::sync::oneshot;
+
+use super::{
+    Config, ConnectionState, EncodeValue, MessageData, PublishProperties, PublishedTopic,
+    SubscriptionOptions, Topic, Type,
+};
+
+/// Current thread runtime driven by a background thread until it is dropped
+#[derive(Debug)]
+struct Runtime {
+    handle: tokio::runtime::Handle,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Runtime {
+    fn new() -> Result<Self, crate::Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let handle = runtime.handle().clone();
+        let (shutdown, shutdown_receiver) = oneshot::channel::<()>();
+
+        let thread = std::thread::Builder::new()
+            .name("network-tables".to_owned())
+            .spawn(move || {
+                // Drives the io & timers for futures run with `Handle::block_on` & tasks spawned by the client
+                runtime.block_on(shutdown_receiver).ok();
+            })?;
+
+        Ok(Self {
+            handle,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.handle.block_on(future)
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+
+        // The runtime and every task of the client are dropped when the thread ends
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Blocking version of [`super::Client`].
+///
+/// Clones share the same connection & runtime, which are shut down when the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct Client {
+    // Dropped before the runtime so nothing is left using it
+    client: super::Client,
+    runtime: Arc<Runtime>,
+}
+
+impl Client {
+    pub fn new(
+        server_addr: impl Into<SocketAddr>,
+        identity: Option<&'static str>,
+    ) -> Result<Self, crate::Error> {
+        Self::new_w_config(server_addr, Config::default(), identity)
+    }
+
+    pub fn new_w_config(
+        server_addr: impl Into<SocketAddr>,
+        config: Config,
+        identity: Option<&'static str>,
+    ) -> Result<Self, crate::Error> {
+        let runtime = Runtime::new()?;
+        let client = runtime.block_on(super::Client::try_new_w_config(
+            server_addr,
+            config,
+            identity,
+        ))?;
+
+        Ok(Self {
+            client,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// The async client, which can be used on the client's runtime through [`Self::block_on`]
+    pub fn as_async(&self) -> &super::Client {
+        &self.client
+    }
+
+    /// Runs a future on the client's runtime, e.g. for async only features
+    pub fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.client.server_addr()
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        self.client.connection_state()
+    }
+
+    /// See [`super::Client::reconnect`]
+    pub fn reconnect(&self) -> Result<(), crate::Error> {
+        self.block_on(self.client.reconnect())
+    }
+
+    pub fn server_time(&self) -> u32 {
+        self.client.server_time()
+    }
+
+    pub fn publish_topic(
+        &self,
+        name: impl AsRef<str>,
+        topic_type: Type,
+        properties: Option<PublishProperties>,
+    ) -> Result<PublishedTopic, crate::Error> {
+        self.block_on(self.client.publish_topic(name, topic_type, properties))
+    }
+
+    pub fn unpublish(&self, topic: PublishedTopic) -> Result<(), crate::Error> {
+        self.block_on(self.client.unpublish(topic))
+    }
+
+    pub fn set_properties(
+        &self,
+        topic: &PublishedTopic,
+        update: PublishProperties,
+    ) -> Result<(), crate::Error> {
+        self.block_on(self.client.set_properties(topic, update))
+    }
+
+    /// See [`super::Client::publish_value`]
+    pub fn publish_value(
+        &self,
+        topic: &PublishedTopic,
+        value: impl EncodeValue,
+    ) -> Result<(), crate::Error> {
+        self.block_on(self.client.publish_value(topic, value))
+    }
+
+    pub fn publish_value_w_timestamp(
+        &self,
+        topic: &PublishedTopic,
+        timestamp: u32,
+        value: impl EncodeValue,
+    ) -> Result<(), crate::Error> {
+        self.block_on(
+            self.client
+                .publish_value_w_timestamp(topic, timestamp, value),
+        )
+    }
+
+    pub fn subscribe(&self, topic_names: &[impl ToString]) -> Result<Subscription, crate::Error> {
+        self.subscribe_w_options(topic_names, None)
+    }
+
+    pub fn subscribe_w_options(
+        &self,
+        topic_names: &[impl ToString],
+        options: Option<SubscriptionOptions>,
+    ) -> Result<Subscription, crate::Error> {
+        let subscription = self.block_on(self.client.subscribe_w_options(topic_names, options))?;
+        Ok(Subscription {
+            subscription,
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    pub fn unsubscribe(&self, sub: Subscription) -> Result<(), crate::Error> {
+        self.block_on(self.client.unsubscribe(sub.subscription))
+    }
+
+    /// See [`super::Client::topic`]
+    pub fn topic(&self, name: &str) -> Option<Topic> {
+        self.client.topic(name)
+    }
+
+    /// See [`super::Client::topics`]
+    pub fn topics(&self, prefix: &str) -> Vec<Topic> {
+        self.client.topics(prefix)
+    }
+}
+
+/// Blocking version of [`super::Subscription`], iterating waits for the next value.
+///
+/// The iterator ends when the client is dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    subscription: super::Subscription,
+    // Values only arrive while the runtime is running
+    runtime: Arc<Runtime>,
+}
+
+impl Subscription {
+    /// Next value if one was already received
+    pub fn try_next(&mut self) -> Option<Arc<MessageData>> {
+        self.subscription.receiver.try_recv().ok()
+    }
+
+    /// Waits up to `timeout` for the next value
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Arc<MessageData>> {
+        let receiver = &mut self.subscription.receiver;
+        // The timer has to be created inside the runtime
+        self.runtime
+            .block_on(async { tokio::time::timeout(timeout, receiver.recv()).await })
+            .ok()
+            .flatten()
+    }
+
+    pub fn into_async(self) -> super::Subscription {
+        self.subscription
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Arc<MessageData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.subscription.receiver.blocking_recv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::*;
+
+    /// Held by tests which start runtime threads, so they don't count each other's
+    static RUNTIME_THREADS: Mutex<()> = Mutex::new(());
+
+    /// Running runtime threads, always 0 where `/proc` doesn't exist
+    fn runtime_threads() -> usize {
+        std::fs::read_dir("/proc/self/task")
+            .into_iter()
+            .flatten()
+            .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+            .filter(|name| name.trim_end() == "network-tables")
+            .count()
+    }
+
+    /// Client without a socket task, the receiver gets the messages it would send to the server
+    fn unconnected() -> (Client, mpsc::Receiver<Message>) {
+        let (client, socket_receiver) = super::super::Client::unconnected();
+        let client = Client {
+            client,
+            runtime: Arc::new(Runtime::new().unwrap()),
+        };
+        (client, socket_receiver)
+    }
+
+    #[test]
+    fn dropping_runtime_joins_thread() {
+        let _guard = RUNTIME_THREADS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let runtime = Runtime::new().unwrap();
+        let (started, started_receiver) = oneshot::channel::<()>();
+        let (task_alive, mut task_dropped) = oneshot::channel::<()>();
+        runtime.handle.spawn(async move {
+            let _task_alive = task_alive;
+            started.send(()).ok();
+            std::future::pending::<()>().await;
+        });
+        started_receiver.blocking_recv().unwrap();
+        #[cfg(target_os = "linux")]
+        assert_eq!(runtime_threads(), 1);
+
+        drop(runtime);
+        // Tasks which never finish are dropped with the runtime
+        assert!(task_dropped.try_recv().is_err());
+        assert_eq!(runtime_threads(), 0);
+    }
+
+    #[test]
+    fn unreachable_server_is_an_error() {
+        let _guard = RUNTIME_THREADS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let config = Config {
+            connect_timeout: 100,
+            ..Default::default()
+        };
+
+        assert!(Client::new_w_config(([127, 0, 0, 1], 1), config, None).is_err());
+        assert_eq!(runtime_threads(), 0);
+    }
+
+    #[test]
+    fn subscription_ends_when_client_is_dropped() {
+        let _guard = RUNTIME_THREADS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let (client, _socket_receiver) = unconnected();
+        let subscription = client.subscribe(&["/a"]).unwrap();
+
+        let reader = std::thread::spawn(move || subscription.count());
+        drop(client);
+        assert_eq!(reader.join().unwrap(), 0);
+        assert_eq!(runtime_threads(), 0);
+    }
+}
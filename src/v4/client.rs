@@ -1,8 +1,8 @@
 use std::{
     borrow::Cow,
-    collections::{HashMap, HashSet, VecDeque},
+    collections::{HashMap, HashSet},
+    future::Future,
     net::SocketAddr,
-    ops::Div,
     sync::{Arc, Weak},
     time::{Duration, Instant},
 };
@@ -10,16 +10,27 @@ use std::{
 use crate::log_result;
 
 use super::{
-    Announce, Config, InternalSub, MessageData, NTMessage, PublishProperties, PublishTopic,
-    PublishedTopic, SetProperties, Subscribe, Subscription, SubscriptionData, SubscriptionOptions,
-    Topic, Type,
+    encode::{self, BufferPool, EncodeValue},
+    frame_batcher::{is_time_sync, FrameBatcher},
+    meta::{MetaSubscription, ServerModel, META_PREFIX},
+    publish_batch::PublishBatch,
+    subscription::{
+        LatestSubscription, LatestValues, SendError, SubSender, SUBSCRIPTION_QUEUE_SIZE,
+    },
+    time_sync::{time_diff, TimeSync, TimeSyncStats},
+    topic::AnnouncedTopics,
+    Announce, Config, ConnectionState, Diagnostic, Entry, InternalSub, MessageData, NTMessage,
+    NtValue, PublishProperties, PublishTopic, PublishedTopic, SetProperties, Subscribe,
+    Subscription, SubscriptionData, SubscriptionOptions, Topic, Type, Unsubscribe,
 };
+use bytes::Bytes;
 use futures_util::{SinkExt, TryStreamExt};
+use rmpv::ValueRef;
+use serde::Deserialize;
 use tokio::{
     net::TcpStream,
     select,
-    sync::{mpsc, oneshot, Mutex},
-    task::yield_now,
+    sync::{broadcast, mpsc, watch, Mutex, Notify},
 };
 use tokio_tungstenite::tungstenite::{client::IntoClientRequest, http::HeaderValue, Message};
 
@@ -33,13 +44,29 @@ type WebSocket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsS
 #[derive(Debug)]
 struct InnerClient {
     server_addr: SocketAddr,
+    // These are read for every value received, so they are never held across an await
     // Keys are subuid, value is a handle to sub data and a sender to the sub's mpsc
-    subscriptions: Mutex<HashMap<i32, InternalSub>>,
-    announced_topics: Mutex<HashMap<i32, Topic>>,
-    client_published_topics: Mutex<HashMap<u32, PublishedTopic>>,
-    socket_sender: mpsc::Sender<Message>,
-    socket_panic_receiver: parking_lot::Mutex<oneshot::Receiver<crate::Error>>,
-    server_time_offset: parking_lot::Mutex<u32>,
+    subscriptions: parking_lot::RwLock<HashMap<i32, InternalSub>>,
+    announced_topics: parking_lot::RwLock<AnnouncedTopics>,
+    // Keys are topic id, values are the subuids of the subscriptions matching that topic.
+    // Filled on announce (or the first value after being cleared) and cleared whenever subscriptions change
+    matching_subscriptions: parking_lot::RwLock<HashMap<i32, Arc<[i32]>>>,
+    client_published_topics: parking_lot::Mutex<HashMap<u32, PublishedTopic>>,
+    // Keys are pubuid, values are our topic & the type the server announced it with, if it's different
+    type_conflicts: parking_lot::RwLock<HashMap<u32, (PublishedTopic, Type)>>,
+    // Replaced when the socket task is restarted by `Client::reconnect`
+    socket_sender: parking_lot::Mutex<mpsc::Sender<Message>>,
+    connection_state: watch::Sender<ConnectionState>,
+    // Held while restarting the socket task so it only happens once
+    restart_lock: Mutex<()>,
+    // Notified whenever the server announces a topic
+    topic_announced: Notify,
+    // Announces for the user's callbacks, which are called on their own task
+    topic_events: mpsc::UnboundedSender<TopicEvent>,
+    diagnostics: broadcast::Sender<Diagnostic>,
+    // Buffers for encoding published values, returned by the socket task after batching
+    buffer_pool: BufferPool,
+    time_sync: parking_lot::Mutex<TimeSync>,
     sub_counter: parking_lot::Mutex<i32>,
     topic_counter: parking_lot::Mutex<u32>,
     config: Config,
@@ -55,61 +82,127 @@ impl Client {
         config: Config,
         identity: Option<&'static str>,
     ) -> Result<Self, crate::Error> {
-        let (socket_sender, socket_receiver) = mpsc::channel::<Message>(100);
-        let (panic_sender, panic_recv) = oneshot::channel::<crate::Error>();
-        let id = rand::random();
-        let inner = Arc::new(InnerClient {
-            server_addr: server_addr.into(),
-            subscriptions: Mutex::new(HashMap::new()),
-            announced_topics: Mutex::new(HashMap::new()),
-            client_published_topics: Mutex::new(HashMap::new()),
-            socket_sender,
-            socket_panic_receiver: parking_lot::Mutex::new(panic_recv),
-            server_time_offset: parking_lot::Mutex::new(0),
-            sub_counter: parking_lot::Mutex::new(0),
-            topic_counter: parking_lot::Mutex::new(0),
-            start_time: parking_lot::Mutex::new(Instant::now()),
-            config,
-            id,
-            identity: identity.unwrap_or_else(|| "rust")
-        });
-        setup_socket(Arc::downgrade(&inner), socket_receiver, panic_sender).await?;
+        let (inner, socket_receiver, topic_events_receiver) =
+            InnerClient::new(server_addr.into(), config, identity);
+        let inner = Arc::new(inner);
+        setup_socket(Arc::downgrade(&inner), socket_receiver).await?;
 
-        inner.on_open().await;
+        inner.on_open().await?;
+
+        // Task to call the user's announce callbacks, so slow callbacks don't hold up values
+        tokio::spawn(call_topic_callbacks(
+            Arc::downgrade(&inner),
+            topic_events_receiver,
+        ));
 
         // Task to handle messages from server
         let timestamp_task_client = Arc::downgrade(&inner);
         tokio::spawn(async move {
-            const TIMESTAMP_INTERVAL: u64 = 5;
             loop {
-                match timestamp_task_client.upgrade() {
-                    Some(client) => client.update_time().await.ok(),
+                let interval = match timestamp_task_client.upgrade() {
+                    Some(client) => {
+                        client.update_time().await.ok();
+                        client.config.time_sync_interval
+                    }
                     None => break,
                 };
 
-                tokio::time::sleep(Duration::from_secs(TIMESTAMP_INTERVAL)).await;
+                // A zero interval would flood the server with time sync requests
+                tokio::time::sleep(Duration::from_millis(interval.max(1))).await;
             }
         });
 
         Ok(Self { inner })
     }
 
-    pub async fn try_new(server_addr: impl Into<SocketAddr>, identity: Option<&'static str>) -> Result<Self, crate::Error> {
+    pub async fn try_new(
+        server_addr: impl Into<SocketAddr>,
+        identity: Option<&'static str>,
+    ) -> Result<Self, crate::Error> {
         Self::try_new_w_config(server_addr, Config::default(), identity).await
     }
 
-    pub async fn new_w_config(server_addr: impl Into<SocketAddr>, config: Config, identity: Option<&'static str>) -> Self {
-        Self::try_new_w_config(server_addr, config, identity).await.unwrap()
+    #[deprecated(note = "use `Client::try_new_w_config`, which this forwards to")]
+    pub async fn new_w_config(
+        server_addr: impl Into<SocketAddr>,
+        config: Config,
+        identity: Option<&'static str>,
+    ) -> Result<Self, crate::Error> {
+        Self::try_new_w_config(server_addr, config, identity).await
     }
 
-    pub async fn new(server_addr: impl Into<SocketAddr>, identity: Option<&'static str>) -> Self {
-        Self::new_w_config(server_addr, Config::default(), identity).await
+    #[deprecated(note = "use `Client::try_new`, which this forwards to")]
+    pub async fn new(
+        server_addr: impl Into<SocketAddr>,
+        identity: Option<&'static str>,
+    ) -> Result<Self, crate::Error> {
+        Self::try_new(server_addr, identity).await
     }
 
     pub fn server_addr(&self) -> SocketAddr {
         self.inner.server_addr
     }
 
+    pub fn connection_state(&self) -> ConnectionState {
+        self.inner.connection_state.borrow().clone()
+    }
+
+    /// Receives every change of the connection state
+    pub fn watch_connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.inner.connection_state.subscribe()
+    }
+
+    /// Restarts the connection after it failed with an error that `should_reconnect` didn't allow reconnecting after.
+    /// Topics and subscriptions are restored like on a normal reconnect.
+    ///
+    /// Does nothing if the connection hasn't failed.
+    pub async fn reconnect(&self) -> Result<(), crate::Error> {
+        let _restarting = self.inner.restart_lock.lock().await;
+        if self.inner.connection_state.borrow().error().is_none() {
+            return Ok(());
+        }
+
+        let (socket_sender, socket_receiver) = mpsc::channel::<Message>(SOCKET_CHANNEL_SIZE);
+        setup_socket(Arc::downgrade(&self.inner), socket_receiver).await?;
+        *self.inner.socket_sender.lock() = socket_sender;
+        self.inner
+            .connection_state
+            .send_replace(ConnectionState::Connected);
+
+        self.inner.on_open().await?;
+        (self.inner.config.on_reconnect)().await;
+
+        Ok(())
+    }
+
+    /// Current time of the server in microseconds, used as the timestamp of published values
+    pub fn server_time(&self) -> u32 {
+        self.inner.server_time()
+    }
+
+    /// Microseconds since this client's time was last reset, which happens on every (re)connect
+    pub fn client_time(&self) -> u32 {
+        self.inner.client_time()
+    }
+
+    /// Server time in microseconds at the given instant, e.g. when a camera frame was captured
+    pub fn server_time_at(&self, instant: Instant) -> u32 {
+        self.inner.server_time_at(instant)
+    }
+
+    /// Local instant of a server timestamp, e.g. of a received value.
+    ///
+    /// Timestamps wrap around every ~71 minutes, so they are taken to be within ~35 minutes of the current server time.
+    /// Returns `None` if the instant can't be represented.
+    pub fn instant_at(&self, server_time: u32) -> Option<Instant> {
+        self.inner.instant_at(server_time)
+    }
+
+    /// Quality of the estimate of the server's time, `None` until the server has answered a time sync request
+    pub fn time_sync_stats(&self) -> Option<TimeSyncStats> {
+        self.inner.time_sync.lock().stats()
+    }
+
     pub async fn publish_topic(
         &self,
         name: impl AsRef<str>,
@@ -138,21 +231,24 @@ impl Client {
         // Put message in an array and serialize
         let message = serde_json::to_string(&messages)?;
 
-        self.inner.send_message(Message::Text(message)).await?;
-
+        // Known before sending, the server's announce can be handled before `send_message` returns
         let topic = PublishedTopic {
             name: name.as_ref().to_owned(),
             pubuid,
             r#type: topic_type,
             properties,
         };
-
         self.inner
             .client_published_topics
             .lock()
-            .await
             .insert(pubuid, topic.clone());
 
+        if let Err(err) = self.inner.send_message(Message::Text(message)).await {
+            self.inner.client_published_topics.lock().remove(&pubuid);
+            self.inner.type_conflicts.write().remove(&pubuid);
+            return Err(err);
+        }
+
         Ok(topic)
     }
 
@@ -162,11 +258,64 @@ impl Client {
 
         self.inner.send_message(Message::Text(message)).await?;
 
+        // Don't republish it on reconnect
+        self.inner
+            .client_published_topics
+            .lock()
+            .remove(&topic.pubuid);
+        self.inner.type_conflicts.write().remove(&topic.pubuid);
+
         Ok(())
     }
 
-    pub async fn set_properties(&self) {
-        todo!()
+    /// Publishes & subscribes to a single topic, returning a handle which can get, set and set a default value
+    pub async fn entry(
+        &self,
+        name: impl AsRef<str>,
+        topic_type: Type,
+        properties: Option<PublishProperties>,
+    ) -> Result<Entry, crate::Error> {
+        // Subscribe first so the server sends us the current value
+        let subscription = self.subscribe(&[name.as_ref()]).await?;
+        // Don't leave the subscription on the server if publishing fails or is cancelled
+        let unsubscribe = UnsubscribeOnDrop::new(self, &subscription);
+        let topic = self.publish_topic(name, topic_type, properties).await?;
+        unsubscribe.disarm();
+
+        Ok(Entry {
+            client: self.clone(),
+            topic,
+            subscription: Some(subscription),
+            latest: None,
+            sync_timeout: Duration::from_millis(self.inner.config.entry_sync_timeout),
+        })
+    }
+
+    /// Updates the properties of a topic this client published
+    pub async fn set_properties(
+        &self,
+        topic: &PublishedTopic,
+        update: PublishProperties,
+    ) -> Result<(), crate::Error> {
+        // Put message in an array and serialize
+        let message = serde_json::to_string(&[NTMessage::SetProperties(SetProperties {
+            name: &topic.name,
+            update: Cow::Borrowed(&update),
+        })])?;
+
+        self.inner.send_message(Message::Text(message)).await?;
+
+        // Keep properties for republishing on reconnect
+        if let Some(published) = self
+            .inner
+            .client_published_topics
+            .lock()
+            .get_mut(&topic.pubuid)
+        {
+            published.properties = Some(update);
+        }
+
+        Ok(())
     }
 
     pub async fn subscribe(
@@ -181,6 +330,34 @@ impl Client {
         topic_names: &[impl ToString],
         options: Option<SubscriptionOptions>,
     ) -> Result<Subscription, crate::Error> {
+        let (sender, receiver) = mpsc::channel::<Arc<MessageData>>(SUBSCRIPTION_QUEUE_SIZE);
+        let data = self
+            .register_subscription(topic_names, options, SubSender::Queue(sender))
+            .await?;
+
+        Ok(Subscription { data, receiver })
+    }
+
+    /// Subscribes keeping only the newest value of each topic instead of queueing every value
+    pub async fn subscribe_latest(
+        &self,
+        topic_names: &[impl ToString],
+        options: Option<SubscriptionOptions>,
+    ) -> Result<LatestSubscription, crate::Error> {
+        let (sender, receiver) = watch::channel(LatestValues::new());
+        let data = self
+            .register_subscription(topic_names, options, SubSender::Latest(sender))
+            .await?;
+
+        Ok(LatestSubscription { data, receiver })
+    }
+
+    async fn register_subscription(
+        &self,
+        topic_names: &[impl ToString],
+        options: Option<SubscriptionOptions>,
+        sender: SubSender,
+    ) -> Result<Arc<SubscriptionData>, crate::Error> {
         let topic_names: Vec<String> = topic_names.into_iter().map(ToString::to_string).collect();
         let subuid = self.inner.new_sub_id();
 
@@ -199,29 +376,52 @@ impl Client {
             topics: HashSet::from_iter(topic_names.into_iter()),
         });
 
-        let (sender, receiver) = mpsc::channel::<MessageData>(256);
-        self.inner.subscriptions.lock().await.insert(
+        self.inner.subscriptions.write().insert(
             subuid,
             InternalSub {
                 data: Arc::downgrade(&data),
                 sender,
             },
         );
+        self.inner.matching_subscriptions.write().clear();
 
-        Ok(Subscription { data, receiver })
+        Ok(data)
+    }
+
+    /// Subscribes to every meta topic, keeping a model of the server's clients, publishers and subscribers
+    pub async fn subscribe_meta(&self) -> Result<MetaSubscription, crate::Error> {
+        let subscription = self
+            .subscribe_w_options(
+                &[META_PREFIX],
+                Some(SubscriptionOptions {
+                    prefix: Some(true),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+
+        Ok(MetaSubscription {
+            subscription,
+            model: ServerModel::default(),
+        })
     }
 
     pub async fn unsubscribe(&self, sub: Subscription) -> Result<(), crate::Error> {
+        self.unsubscribe_uid(sub.data.subuid).await
+    }
+
+    pub async fn unsubscribe_latest(&self, sub: LatestSubscription) -> Result<(), crate::Error> {
+        self.unsubscribe_uid(sub.data.subuid).await
+    }
+
+    pub(crate) async fn unsubscribe_uid(&self, subuid: i32) -> Result<(), crate::Error> {
         // Put message in an array and serialize
-        let message = serde_json::to_string(&[sub.as_unsubscribe()])?;
+        let message = serde_json::to_string(&[NTMessage::Unsubscribe(Unsubscribe { subuid })])?;
         self.inner.send_message(Message::Text(message)).await?;
 
         // Remove from our subscriptions
-        self.inner
-            .subscriptions
-            .lock()
-            .await
-            .remove(&sub.data.subuid);
+        self.inner.subscriptions.write().remove(&subuid);
+        self.inner.matching_subscriptions.write().clear();
 
         Ok(())
     }
@@ -230,68 +430,377 @@ impl Client {
         &self,
         topic: &PublishedTopic,
         timestamp: u32,
-        value: &rmpv::Value,
+        value: impl EncodeValue,
     ) -> Result<(), crate::Error> {
         self.inner
-            .publish_value_w_timestamp(
-                UnsignedIntOrNegativeOne::UnsignedInt(topic.pubuid),
-                topic.r#type,
-                timestamp,
-                value,
-            )
+            .publish_value_w_timestamp(topic.pubuid.into(), &topic.r#type, timestamp, value)
             .await
     }
 
-    /// Value should match topic type
+    /// Value should match topic type, otherwise [`crate::Error::TypeMismatch`] is returned.
+    /// Numeric values are converted for `double` & `float` topics.
+    ///
+    /// Accepts a [`NtValue`] or plain values such as `f64`, `&str` and `&[f64]`,
+    /// which are written straight into a reused buffer without building a [`NtValue`] first.
     pub async fn publish_value(
         &self,
         topic: &PublishedTopic,
-        value: &rmpv::Value,
+        value: impl EncodeValue,
     ) -> Result<(), crate::Error> {
         self.inner
-            .publish_value(
-                UnsignedIntOrNegativeOne::UnsignedInt(topic.pubuid),
-                topic.r#type,
-                value,
-            )
+            .publish_value(topic.pubuid.into(), &topic.r#type, value)
             .await
     }
 
-    pub async fn use_announced_topics<F: Fn(&HashMap<i32, Topic>)>(&self, f: F) {
-        f(&*self.inner.announced_topics.lock().await)
+    /// Publishes without waiting, for use outside of async code such as a control loop thread.
+    /// Returns [`crate::Error::QueueFull`] if the socket task is behind, the value is not sent then.
+    pub fn try_publish_value(
+        &self,
+        topic: &PublishedTopic,
+        value: impl EncodeValue,
+    ) -> Result<(), crate::Error> {
+        self.try_publish_value_w_timestamp(topic, self.server_time(), value)
+    }
+
+    pub fn try_publish_value_w_timestamp(
+        &self,
+        topic: &PublishedTopic,
+        timestamp: u32,
+        value: impl EncodeValue,
+    ) -> Result<(), crate::Error> {
+        self.inner.try_publish_value_w_timestamp(
+            topic.pubuid.into(),
+            &topic.r#type,
+            timestamp,
+            value,
+        )
+    }
+
+    /// Starts a batch of values which are published with the current server time in one frame
+    pub fn publish_batch(&self) -> PublishBatch<'_> {
+        self.publish_batch_w_timestamp(self.server_time())
+    }
+
+    pub fn publish_batch_w_timestamp(&self, timestamp: u32) -> PublishBatch<'_> {
+        PublishBatch::new(self, timestamp, self.inner.buffer_pool.take())
+    }
+
+    pub(crate) async fn send_encoded(&self, buf: Vec<u8>) -> Result<(), crate::Error> {
+        self.inner.send_encoded(buf).await
+    }
+
+    pub(crate) fn downgrade(&self) -> WeakClient {
+        WeakClient {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
+
+    pub(crate) fn recycle_buffer(&self, buf: Vec<u8>) {
+        self.inner.buffer_pool.recycle(buf);
+    }
+
+    /// Type the server announced for a topic we published with a different type.
+    /// Values can't be published to the topic while there is a conflict.
+    pub fn type_conflict(&self, topic: &PublishedTopic) -> Option<Type> {
+        self.inner
+            .type_conflicts
+            .read()
+            .get(&topic.pubuid)
+            .map(|(_, announced)| announced.clone())
+    }
+
+    pub(crate) fn check_type_conflict(&self, topic: &PublishedTopic) -> Result<(), crate::Error> {
+        self.inner.check_type_conflict(topic.pubuid.into())
+    }
+
+    /// Receives problems with messages from the server which were skipped, such as unknown methods
+    pub fn diagnostics(&self) -> broadcast::Receiver<Diagnostic> {
+        self.inner.diagnostics.subscribe()
+    }
+
+    /// Topic currently announced by the server with the given name
+    pub fn topic(&self, name: &str) -> Option<Topic> {
+        self.inner
+            .announced_topics
+            .read()
+            .get_by_name(name)
+            .cloned()
+    }
+
+    /// Topics currently announced by the server whose name starts with `prefix`, sorted by name
+    pub fn topics(&self, prefix: &str) -> Vec<Topic> {
+        self.inner
+            .announced_topics
+            .read()
+            .with_prefix(prefix)
+            .cloned()
+            .collect()
+    }
+
+    /// Waits until the server announces a topic, returning [`crate::Error::Timeout`] if it takes longer than `timeout`.
+    ///
+    /// Cancel safe, the temporary subscription is removed when the future completes or is dropped.
+    pub async fn wait_for_topic(
+        &self,
+        name: &str,
+        timeout: Duration,
+    ) -> Result<Topic, crate::Error> {
+        tokio::time::timeout(timeout, async {
+            if let Some(topic) = self.topic(name) {
+                return Ok(topic);
+            }
+
+            // The server only announces topics we are subscribed to
+            let options = SubscriptionOptions {
+                topics_only: Some(true),
+                ..Default::default()
+            };
+            let subscription = self.subscribe_w_options(&[name], Some(options)).await?;
+            let _unsubscribe = UnsubscribeOnDrop::new(self, &subscription);
+
+            Ok(self.wait_for_announce(name, |_| true).await)
+        })
+        .await
+        .map_err(|_| crate::Error::Timeout)?
+    }
+
+    /// Waits until the server has announced the topic in a way matching `predicate`
+    pub(crate) async fn wait_for_announce(
+        &self,
+        name: &str,
+        predicate: impl Fn(&Topic) -> bool,
+    ) -> Topic {
+        loop {
+            let notified = self.inner.topic_announced.notified();
+            tokio::pin!(notified);
+            // Register before checking so an announce in between isn't missed
+            notified.as_mut().enable();
+            if let Some(topic) = self.topic(name).filter(&predicate) {
+                return topic;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Waits until a value of the topic matches `predicate`, including the value the topic has when this is called.
+    /// Returns [`crate::Error::Timeout`] if it takes longer than `timeout`.
+    ///
+    /// Cancel safe, the temporary subscription is removed when the future completes or is dropped.
+    pub async fn wait_for_value<F>(
+        &self,
+        name: &str,
+        mut predicate: F,
+        timeout: Duration,
+    ) -> Result<Arc<MessageData>, crate::Error>
+    where
+        F: FnMut(&NtValue) -> bool,
+    {
+        tokio::time::timeout(timeout, async {
+            let mut subscription = self.subscribe(&[name]).await?;
+            let _unsubscribe = UnsubscribeOnDrop::new(self, &subscription);
+
+            while let Some(message) = subscription.next().await {
+                if predicate(&message.data) {
+                    return Ok(message);
+                }
+            }
+
+            Err(crate::Error::Closed)
+        })
+        .await
+        .map_err(|_| crate::Error::Timeout)?
+    }
+}
+
+/// Runs cleanup which sends messages, such as unsubscribing, from a `Drop` impl.
+/// Messages can only be sent in a runtime, so nothing is done outside of one.
+pub(crate) fn spawn_cleanup(cleanup: impl Future<Output = ()> + Send + 'static) {
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        handle.spawn(cleanup);
+    }
+}
+
+/// Unsubscribes when dropped, so futures using a temporary subscription can be cancelled
+struct UnsubscribeOnDrop {
+    // `None` once disarmed
+    client: Option<Client>,
+    subuid: i32,
+}
+
+impl UnsubscribeOnDrop {
+    fn new(client: &Client, subscription: &Subscription) -> Self {
+        Self {
+            client: Some(client.clone()),
+            subuid: subscription.data.subuid,
+        }
+    }
+
+    /// Keeps the subscription, for when it is handed to the caller
+    fn disarm(mut self) {
+        self.client = None;
+    }
+}
+
+impl Drop for UnsubscribeOnDrop {
+    fn drop(&mut self) {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => return,
+        };
+        let subuid = self.subuid;
+        spawn_cleanup(async move {
+            client.unsubscribe_uid(subuid).await.ok();
+        });
     }
 }
 
 impl InnerClient {
+    /// Client state without a connection.
+    /// Also returns the receivers of the socket task & the task calling the announce callbacks.
+    fn new(
+        server_addr: SocketAddr,
+        config: Config,
+        identity: Option<&'static str>,
+    ) -> (
+        Self,
+        mpsc::Receiver<Message>,
+        mpsc::UnboundedReceiver<TopicEvent>,
+    ) {
+        let (socket_sender, socket_receiver) = mpsc::channel::<Message>(SOCKET_CHANNEL_SIZE);
+        let (topic_events, topic_events_receiver) = mpsc::unbounded_channel();
+        let inner = Self {
+            server_addr,
+            subscriptions: parking_lot::RwLock::new(HashMap::new()),
+            announced_topics: parking_lot::RwLock::new(AnnouncedTopics::default()),
+            matching_subscriptions: parking_lot::RwLock::new(HashMap::new()),
+            client_published_topics: parking_lot::Mutex::new(HashMap::new()),
+            type_conflicts: parking_lot::RwLock::new(HashMap::new()),
+            socket_sender: parking_lot::Mutex::new(socket_sender),
+            connection_state: watch::channel(ConnectionState::Connected).0,
+            restart_lock: Mutex::new(()),
+            topic_announced: Notify::new(),
+            topic_events,
+            diagnostics: broadcast::channel(64).0,
+            buffer_pool: BufferPool::new(),
+            time_sync: parking_lot::Mutex::new(TimeSync::new(config.time_sync_samples)),
+            sub_counter: parking_lot::Mutex::new(0),
+            topic_counter: parking_lot::Mutex::new(0),
+            start_time: parking_lot::Mutex::new(config.clock.now()),
+            config,
+            id: rand::random(),
+            identity: identity.unwrap_or_else(|| "rust"),
+        };
+
+        (inner, socket_receiver, topic_events_receiver)
+    }
+
+    pub(crate) fn report(&self, diagnostic: Diagnostic) {
+        cfg_tracing! {
+            tracing::warn!("Problem with message from server: {diagnostic:?}");
+        }
+
+        // Nobody listening is fine
+        self.diagnostics.send(diagnostic).ok();
+    }
+
+    /// Subuids of the subscriptions matching a topic
+    fn matching_subscriptions(&self, topic_id: i32, topic_name: &str) -> Arc<[i32]> {
+        if let Some(subuids) = self.matching_subscriptions.read().get(&topic_id) {
+            return subuids.clone();
+        }
+
+        // Subscriptions are read while holding the cache lock so a subscription added meanwhile
+        // can't be missing from the cache after it is cleared
+        let mut matching_subscriptions = self.matching_subscriptions.write();
+        let subuids: Arc<[i32]> = self
+            .subscriptions
+            .read()
+            .iter()
+            .filter(|(_, sub)| sub.matches_topic(topic_name))
+            .map(|(subuid, _)| *subuid)
+            .collect();
+        matching_subscriptions.insert(topic_id, subuids.clone());
+        subuids
+    }
+
     /// Returns err if the socket task has ended
-    fn check_task_panic(&self) -> Result<(), crate::Error> {
-        match self.socket_panic_receiver.lock().try_recv() {
-            Ok(err) => Err(err),
-            Err(_) => Ok(()),
+    fn check_connection(&self) -> Result<(), crate::Error> {
+        match self.connection_state.borrow().error() {
+            Some(err) => Err(crate::Error::ConnectionFailed(err.clone())),
+            None => Ok(()),
         }
     }
 
     /// Sends message to websocket task, which handles reconnection if necessary
     pub(crate) async fn send_message(&self, message: Message) -> Result<(), crate::Error> {
-        self.check_task_panic()?;
+        self.check_connection()?;
         cfg_tracing! {
             tracing::trace!("Sending message: {message:?}");
         }
 
-        // Should never be dropped before a send goes off
-        self.socket_sender.send(message).await.unwrap();
+        // Only fails if the socket task has ended
+        let socket_sender = self.socket_sender.lock().clone();
+        if socket_sender.send(message).await.is_err() {
+            self.check_connection()?;
+            return Err(crate::Error::Closed);
+        }
+
         Ok(())
     }
 
+    /// Sends message to websocket task without waiting, returning a binary message's buffer to the pool if it wasn't sent
+    pub(crate) fn try_send_message(&self, message: Message) -> Result<(), crate::Error> {
+        self.check_connection()?;
+
+        let socket_sender = self.socket_sender.lock().clone();
+        let (err, message) = match socket_sender.try_send(message) {
+            Ok(_) => return Ok(()),
+            Err(mpsc::error::TrySendError::Full(message)) => (crate::Error::QueueFull, message),
+            Err(mpsc::error::TrySendError::Closed(message)) => {
+                let err = self
+                    .check_connection()
+                    .err()
+                    .unwrap_or(crate::Error::Closed);
+                (err, message)
+            }
+        };
+        if let Message::Binary(buf) = message {
+            self.buffer_pool.recycle(buf);
+        }
+        Err(err)
+    }
+
     #[inline]
     pub(crate) fn client_time(&self) -> u32 {
-        Instant::now()
-            .duration_since(*self.start_time.lock())
+        self.client_time_at(self.config.clock.now())
+    }
+
+    pub(crate) fn client_time_at(&self, instant: Instant) -> u32 {
+        instant
+            .saturating_duration_since(*self.start_time.lock())
             .as_micros() as u32
     }
 
     pub(crate) fn server_time(&self) -> u32 {
-        self.client_time() + *self.server_time_offset.lock()
+        self.server_time_at(self.config.clock.now())
+    }
+
+    pub(crate) fn server_time_at(&self, instant: Instant) -> u32 {
+        self.client_time_at(instant)
+            .wrapping_add(self.time_sync.lock().offset())
+    }
+
+    /// Counts back from now, so it stays right after client time wraps around
+    pub(crate) fn instant_at(&self, server_time: u32) -> Option<Instant> {
+        let now = self.config.clock.now();
+        let age = time_diff(self.server_time_at(now), server_time);
+        let age_micros = Duration::from_micros(age.unsigned_abs());
+        if age >= 0 {
+            now.checked_sub(age_micros)
+        } else {
+            now.checked_add(age_micros)
+        }
     }
 
     /// Takes new timestamp value and updates this client's offset
@@ -303,12 +812,11 @@ impl InnerClient {
     ) -> Option<()> {
         if let Some(client_timestamp) = client_timestamp {
             let receive_time = self.client_time();
-            let round_trip_time = receive_time.checked_sub(client_timestamp as u32)?;
-            let server_time_at_receive = server_timestamp.checked_sub(round_trip_time.div(2))?;
-
-            // Checked sub because if start_time was too long ago, it will overflow and panic
-            let offset = server_time_at_receive.checked_sub(receive_time)?;
-            *self.server_time_offset.lock() = offset;
+            self.time_sync.lock().add_sample(
+                client_timestamp as u32,
+                server_timestamp,
+                receive_time,
+            )?;
         }
 
         Some(())
@@ -328,81 +836,187 @@ impl InnerClient {
         new_id
     }
 
+    /// `id` is the pubuid of the topic, or -1 for time sync
     pub(crate) async fn publish_value_w_timestamp(
         &self,
-        id: UnsignedIntOrNegativeOne,
-        r#type: Type,
+        id: i64,
+        r#type: &Type,
         timestamp: u32,
-        value: &rmpv::Value,
+        value: impl EncodeValue,
     ) -> Result<(), crate::Error> {
-        self.check_task_panic()?;
-        let mut buf = Vec::<u8>::with_capacity(19);
+        let buf = self.encode_value(id, r#type, timestamp, value)?;
+        self.send_encoded(buf).await
+    }
 
-        // TODO: too lazy to handle these errors 😴
-        rmp::encode::write_array_len(&mut buf, 4).unwrap();
-        // Client side topic is guaranteed to have a uid
-        id.write_to_buf(&mut buf).unwrap();
-        rmp::encode::write_u32(&mut buf, timestamp as u32).unwrap();
-        rmp::encode::write_u32(&mut buf, r#type.as_u8() as u32).unwrap();
-        rmpv::encode::write_value(&mut buf, value).unwrap();
+    /// Like [`Self::publish_value_w_timestamp`], but returns [`crate::Error::QueueFull`] instead of waiting
+    pub(crate) fn try_publish_value_w_timestamp(
+        &self,
+        id: i64,
+        r#type: &Type,
+        timestamp: u32,
+        value: impl EncodeValue,
+    ) -> Result<(), crate::Error> {
+        let buf = self.encode_value(id, r#type, timestamp, value)?;
+        self.try_send_message(Message::Binary(buf))
+    }
 
-        Ok(self.send_message(Message::Binary(buf)).await?)
+    fn encode_value(
+        &self,
+        id: i64,
+        r#type: &Type,
+        timestamp: u32,
+        value: impl EncodeValue,
+    ) -> Result<Vec<u8>, crate::Error> {
+        self.check_connection()?;
+        self.check_type_conflict(id)?;
+        // Returned to the pool by the socket task once it has been copied into a frame
+        let mut buf = self.buffer_pool.take();
+        if let Err(err) = encode::write_value_message(&mut buf, id, timestamp, r#type, value) {
+            self.buffer_pool.recycle(buf);
+            return Err(err);
+        }
+
+        Ok(buf)
     }
 
-    /// Value should match topic type
+    /// Returns err if the server announced the topic published as `pubuid` with a different type,
+    /// since it would reject the values
+    pub(crate) fn check_type_conflict(&self, pubuid: i64) -> Result<(), crate::Error> {
+        let pubuid = match u32::try_from(pubuid) {
+            Ok(pubuid) => pubuid,
+            // Time sync
+            Err(_) => return Ok(()),
+        };
+
+        match self.type_conflicts.read().get(&pubuid) {
+            Some((published, announced)) => Err(crate::Error::TypeConflict {
+                topic: published.name.clone(),
+                published: published.r#type.clone(),
+                announced: announced.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Remembers a conflict if the server announced a topic we published with a different type.
+    ///
+    /// Types are compared by their full type string like the server does, so types sent the same way
+    /// (e.g. `raw` & `struct:Pose2d`, or `string` & `json`) still conflict.
+    fn detect_type_conflict(&self, pubuid: Option<i32>, announced: &Type) {
+        let pubuid = match pubuid.and_then(|pubuid| u32::try_from(pubuid).ok()) {
+            Some(pubuid) => pubuid,
+            None => return,
+        };
+
+        let published = match self.client_published_topics.lock().get(&pubuid) {
+            Some(topic) if topic.r#type != *announced => topic.clone(),
+            _ => return,
+        };
+
+        let is_new = self
+            .type_conflicts
+            .write()
+            .insert(pubuid, (published.clone(), announced.clone()))
+            .is_none();
+        if is_new {
+            self.report(Diagnostic::TypeConflict {
+                topic: published.name,
+                published: published.r#type,
+                announced: announced.clone(),
+            });
+        }
+    }
+
+    /// Sends msgpack messages encoded into a buffer from `buffer_pool` as one binary frame
+    pub(crate) async fn send_encoded(&self, buf: Vec<u8>) -> Result<(), crate::Error> {
+        self.send_message(Message::Binary(buf)).await
+    }
+
+    /// Value should match topic type, it is checked in [`Self::publish_value_w_timestamp`]
     pub(crate) async fn publish_value(
         &self,
-        id: UnsignedIntOrNegativeOne,
-        r#type: Type,
-        value: &rmpv::Value,
+        id: i64,
+        r#type: &Type,
+        value: impl EncodeValue,
     ) -> Result<(), crate::Error> {
         self.publish_value_w_timestamp(id, r#type, self.server_time(), value)
             .await
     }
 
+    /// Websocket request for connecting to the server
+    fn connect_request(
+        &self,
+    ) -> Result<tokio_tungstenite::tungstenite::handshake::client::Request, crate::Error> {
+        let mut request = format!("ws://{}/nt/{}-{}", self.server_addr, self.identity, self.id)
+            .into_client_request()?;
+        // Add sub-protocol header
+        request.headers_mut().append(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static("networktables.first.wpi.edu"),
+        );
+
+        Ok(request)
+    }
+
     fn reset_time(&self) {
-        *self.server_time_offset.lock() = 0;
-        *self.start_time.lock() = Instant::now();
+        self.time_sync.lock().reset();
+        *self.start_time.lock() = self.config.clock.now();
+    }
+
+    /// Moves the start time to now so client time doesn't overflow, keeping the server time offset valid
+    fn rebase_time(&self) {
+        let mut start_time = self.start_time.lock();
+        let now = self.config.clock.now();
+        let elapsed = now.saturating_duration_since(*start_time).as_micros() as u32;
+        *start_time = now;
+        self.time_sync.lock().shift(elapsed);
+    }
+
+    fn time_sync_message(&self) -> Result<Message, crate::Error> {
+        // Time sync messages use id -1 and are always ints
+        let buf = self.encode_value(-1, &Type::Int, 0, i64::from(self.client_time()))?;
+        Ok(Message::Binary(buf))
     }
 
     pub(crate) async fn update_time(&self) -> Result<(), crate::Error> {
-        let announced_topics = self.announced_topics.lock().await;
-        let time_topic = announced_topics.get(&-1);
+        cfg_tracing! {
+            tracing::trace!("Updating timestamp.");
+        }
 
-        if let Some(time_topic) = time_topic {
-            cfg_tracing! {
-                tracing::trace!("Updating timestamp.");
-            }
+        self.send_message(self.time_sync_message()?).await
+    }
+
+    /// Resets our time & state for a new connection.
+    /// Returns the messages to send before any other: a time sync request & the message restoring our topics and subscriptions
+    fn open_messages(&self) -> Result<[Message; 2], crate::Error> {
+        let message = self.reset_state()?;
+        self.reset_time();
+
+        Ok([self.time_sync_message()?, Message::Text(message)])
+    }
 
-            return self
-                .publish_value_w_timestamp(
-                    UnsignedIntOrNegativeOne::NegativeOne,
-                    time_topic.r#type,
-                    0,
-                    &rmpv::Value::Integer(self.client_time().into()),
-                )
-                .await;
+    /// Called on connection open from outside the socket task, which writes [`Self::open_messages`] to the socket itself
+    pub(crate) async fn on_open(&self) -> Result<(), crate::Error> {
+        for message in self.open_messages()? {
+            self.send_message(message).await?;
+        }
+
+        cfg_tracing! {
+            tracing::info!("Prepared new connection.");
         }
 
         Ok(())
     }
 
-    // Called on connection open, must not fail!
-    pub(crate) async fn on_open(&self) {
-        let mut announced = self.announced_topics.lock().await;
-        let client_published = self.client_published_topics.lock().await;
-        let mut subscriptions = self.subscriptions.lock().await;
+    /// Clears announced topics & returns the message which restores our topics and subscriptions
+    fn reset_state(&self) -> Result<String, serde_json::Error> {
+        self.matching_subscriptions.write().clear();
+        // Our topics are announced again after being republished
+        self.type_conflicts.write().clear();
+        let mut announced = self.announced_topics.write();
+        let client_published = self.client_published_topics.lock();
+        let mut subscriptions = self.subscriptions.write();
         announced.clear();
-        announced.insert(
-            -1,
-            Topic {
-                id: -1,
-                name: "Time".into(),
-                pubuid: Some(-1),
-                r#type: Type::Int,
-                properties: None,
-            },
-        );
 
         // One allocation
         let mut messages: Vec<NTMessage> =
@@ -415,7 +1029,7 @@ impl InnerClient {
                 properties: Cow::Borrowed(&topic.properties),
                 // Client published is guaranteed to have a uid
                 pubuid: topic.pubuid,
-                r#type: topic.r#type,
+                r#type: topic.r#type.clone(),
             }));
         }
 
@@ -435,17 +1049,19 @@ impl InnerClient {
             None
         }));
 
-        // Reset our time stuff & send all messages at once (please don't fail 🥺)
-        self.reset_time();
-        drop(announced);
-        self.update_time().await.ok();
-        self.send_message(Message::Text(serde_json::to_string(&messages).unwrap()))
-            .await
-            .ok();
+        serde_json::to_string(&messages)
+    }
+}
 
-        cfg_tracing! {
-            tracing::info!("Prepared new connection.");
-        }
+/// Handle to a client which doesn't keep its connection open, for tasks which should end with the client
+#[derive(Debug, Clone)]
+pub(crate) struct WeakClient {
+    inner: Weak<InnerClient>,
+}
+
+impl WeakClient {
+    pub(crate) fn upgrade(&self) -> Option<Client> {
+        self.inner.upgrade().map(|inner| Client { inner })
     }
 }
 
@@ -457,22 +1073,55 @@ impl Clone for Client {
     }
 }
 
+#[derive(Debug)]
+enum TopicEvent {
+    Announce(Topic),
+    UnAnnounce(Option<Topic>),
+}
+
+/// Calls `on_announce` & `on_un_announce` in the order the server sent the announces
+async fn call_topic_callbacks(
+    client: Weak<InnerClient>,
+    mut receiver: mpsc::UnboundedReceiver<TopicEvent>,
+) {
+    while let Some(event) = receiver.recv().await {
+        let client = match client.upgrade() {
+            Some(client) => client,
+            None => break,
+        };
+
+        match event {
+            TopicEvent::Announce(topic) => (client.config.on_announce)(&topic).await,
+            TopicEvent::UnAnnounce(topic) => (client.config.on_un_announce)(topic).await,
+        }
+    }
+}
+
 /// Handles messages from the server
-async fn handle_message(client: Arc<InnerClient>, message: Message) {
+fn handle_message(client: Arc<InnerClient>, message: Message) {
     match message {
         Message::Text(message) => {
             // Either announce, unannounce, or properties
-            let messages: Vec<NTMessage> = match log_result(
+            // Each message is parsed on its own so one bad message doesn't drop the whole frame
+            let values: Vec<serde_json::Value> = match log_result(
                 serde_json::from_str(&message).map_err(Into::<crate::Error>::into),
             ) {
-                Ok(messages) => messages,
+                Ok(values) => values,
                 Err(_) => {
                     cfg_tracing! {tracing::error!("Server sent an invalid message: {message:?}");}
                     return;
                 }
             };
 
-            for message in messages {
+            for value in &values {
+                let message = match NTMessage::deserialize(value) {
+                    Ok(message) => message,
+                    Err(err) => {
+                        client.report(Diagnostic::from_parse_error(value, err));
+                        continue;
+                    }
+                };
+
                 match message {
                     NTMessage::Announce(Announce {
                         name,
@@ -481,46 +1130,62 @@ async fn handle_message(client: Arc<InnerClient>, message: Message) {
                         properties,
                         r#type,
                     }) => {
-                        let mut announced = client.announced_topics.lock().await;
-
                         cfg_tracing! {
                             tracing::debug!("Server announced: {name}");
                         }
 
-                        if let Some(existing) = announced.get_mut(&id) {
-                            // use server's pubuid if it sent one
-                            if pubuid.is_some() {
-                                existing.pubuid = pubuid;
-                            };
-                        } else {
-                            announced.insert(
+                        client.detect_type_conflict(pubuid, &r#type);
+
+                        let topic = {
+                            let mut announced = client.announced_topics.write();
+                            // Keep our pubuid if the server re-announced the topic because of another publisher
+                            let pubuid = pubuid.or_else(|| {
+                                announced
+                                    .get(id)
+                                    .filter(|existing| existing.name == name)
+                                    .and_then(|existing| existing.pubuid)
+                            });
+                            let topic = Topic {
+                                name: name.to_owned(),
                                 id,
-                                Topic {
-                                    name: name.to_owned(),
-                                    id,
-                                    pubuid,
-                                    properties: Some(properties),
-                                    r#type,
-                                },
-                            );
-                        }
+                                pubuid,
+                                properties: Some(properties),
+                                r#type,
+                            };
 
-                        // Call user provided on announce fn
-                        (client.config.on_announce)(announced.get(&id).unwrap()).await;
+                            announced.insert(topic.clone());
+                            topic
+                        };
+                        client.topic_announced.notify_waiters();
+
+                        // The topic might have been re-announced with a new name
+                        client.matching_subscriptions.write().remove(&id);
+                        client.matching_subscriptions(id, name);
+
+                        client.topic_events.send(TopicEvent::Announce(topic)).ok();
                     }
                     NTMessage::UnAnnounce(un_announce) => {
                         cfg_tracing! {
                             tracing::debug!("Server un_announced: {}", un_announce.name);
                         }
 
-                        let removed = client.announced_topics.lock().await.remove(&un_announce.id);
-                        (client.config.on_un_announce)(removed).await;
+                        let removed = client.announced_topics.write().remove(un_announce.id);
+                        client
+                            .matching_subscriptions
+                            .write()
+                            .remove(&un_announce.id);
+                        client
+                            .topic_events
+                            .send(TopicEvent::UnAnnounce(removed))
+                            .ok();
                     }
                     NTMessage::Properties(_) => {
                         // I don't need to do anything
                     }
                     _ => {
-                        cfg_tracing! {tracing::error!("Server sent an invalid message: {message:?}");}
+                        client.report(Diagnostic::UnexpectedMethod {
+                            method: value["method"].as_str().unwrap_or_default().to_owned(),
+                        });
                     }
                 }
             }
@@ -528,16 +1193,21 @@ async fn handle_message(client: Arc<InnerClient>, message: Message) {
         Message::Binary(msgpack) => {
             // Message pack value, update
 
-            // Put the raw data in a VecDeque because its read impl removes bytes from it
-            // so we keep deserializing msgpack from the VecDeque until it is emptied out
-            let mut msgpack = VecDeque::from(msgpack);
-            while let Ok(data) = rmp_serde::decode::from_read(&mut msgpack) {
-                match data {
-                    rmpv::Value::Array(array) => handle_value(array, Arc::clone(&client)).await,
-                    _ => {
+            // Values are decoded straight out of the frame, which raw values keep sharing
+            let frame = Bytes::from(msgpack);
+            let mut remaining: &[u8] = &frame;
+            while !remaining.is_empty() {
+                match rmpv::decode::read_value_ref(&mut remaining) {
+                    Ok(ValueRef::Array(array)) => handle_value(&array, &frame, &client),
+                    Ok(value) => client.report(Diagnostic::InvalidMessage {
+                        message: serde_json::json!({ "value": value.to_string() }),
+                        error: "value update is not an array".to_owned(),
+                    }),
+                    Err(_) => {
                         cfg_tracing! {
-                            tracing::error!("Server sent an invalid msgpack data, not an array.");
+                            tracing::error!("Server sent invalid msgpack data.");
                         }
+                        break;
                     }
                 }
             }
@@ -546,7 +1216,7 @@ async fn handle_message(client: Arc<InnerClient>, message: Message) {
     }
 }
 
-async fn handle_value(array: Vec<rmpv::Value>, client: Arc<InnerClient>) {
+fn handle_value(array: &[ValueRef<'_>], frame: &Bytes, client: &Arc<InnerClient>) {
     if array.len() != 4 {
         cfg_tracing! {
             tracing::error!("Server sent an invalid msgpack data, wrong length.");
@@ -554,7 +1224,7 @@ async fn handle_value(array: Vec<rmpv::Value>, client: Arc<InnerClient>) {
         return;
     }
 
-    let id = array[0].as_i64().map(|n| n as i32);
+    let id = as_i64(&array[0]).map(|n| n as i32);
     let timestamp_micros = array[1].as_u64().map(|n| n as u32);
     let type_idx = array[2].as_u64();
     let data = &array[3];
@@ -563,18 +1233,24 @@ async fn handle_value(array: Vec<rmpv::Value>, client: Arc<InnerClient>) {
         if let Some(timestamp_micros) = timestamp_micros {
             if id >= 0 {
                 if let Some(type_idx) = type_idx {
-                    let r#type = Type::from_num(type_idx);
-                    if let Some(r#type) = r#type {
-                        if let Some(topic) = client.announced_topics.lock().await.get(&id) {
-                            cfg_tracing! {tracing::trace!("Received Value: {topic:?} {type:?} {data:?}");}
+                    // Only used to validate, the announced type is more specific
+                    if Type::from_num(type_idx).is_some() {
+                        let topic = client
+                            .announced_topics
+                            .read()
+                            .get(id)
+                            .map(|topic| (topic.name.clone(), topic.r#type.clone()));
+                        if let Some((name, r#type)) = topic {
+                            cfg_tracing! {tracing::trace!("Received Value: {name} {type:?} {type_idx} {data:?}");}
                             send_value_to_subscriber(
-                                client.clone(),
-                                topic,
-                                timestamp_micros,
+                                client,
+                                id,
+                                name,
                                 r#type,
+                                timestamp_micros,
                                 data,
-                            )
-                            .await;
+                                frame,
+                            );
                         } else {
                             cfg_tracing! {
                                 tracing::error!("Received a topic before it was announced! 😱");
@@ -589,13 +1265,17 @@ async fn handle_value(array: Vec<rmpv::Value>, client: Arc<InnerClient>) {
                 }
             } else if id == -1 {
                 // Timestamp update
-                match client.handle_new_timestamp(timestamp_micros, data.as_i64()) {
+                let client_timestamp = as_i64(data);
+                match client.handle_new_timestamp(timestamp_micros, client_timestamp) {
                     Some(_) => {}
                     None => {
-                        // Math failed, update most recent time
-                        *client.start_time.lock() = Instant::now();
-                        client.update_time().await.ok();
-                        client.handle_new_timestamp(timestamp_micros, data.as_i64());
+                        // Client time overflowed, restart it & sync again.
+                        // Waiting for room in the queue would block the socket task which empties it,
+                        // if it's full the next periodic sync is used instead
+                        client.rebase_time();
+                        if let Ok(message) = client.time_sync_message() {
+                            client.try_send_message(message).ok();
+                        }
                     }
                 };
             } else {
@@ -604,43 +1284,86 @@ async fn handle_value(array: Vec<rmpv::Value>, client: Arc<InnerClient>) {
                     tracing::error!("Server sent an invalid topic id, less than -1");
                 }
             };
-
-            return;
         }
+    }
+}
 
-        return;
+fn as_i64(value: &ValueRef) -> Option<i64> {
+    match value {
+        ValueRef::Integer(int) => int.as_i64(),
+        _ => None,
     }
 }
 
-async fn send_value_to_subscriber(
-    client: Arc<InnerClient>,
-    topic: &Topic,
-    timestamp_micros: u32,
+fn send_value_to_subscriber(
+    client: &InnerClient,
+    topic_id: i32,
+    topic_name: String,
     r#type: Type,
-    data: &rmpv::Value,
+    timestamp_micros: u32,
+    data: &ValueRef<'_>,
+    frame: &Bytes,
 ) {
-    // Allows sent values to be handled by subs, cause there hasnt been an await for a while
-    yield_now().await;
+    let subuids = client.matching_subscriptions(topic_id, &topic_name);
+    if subuids.is_empty() {
+        return;
+    }
 
-    client.subscriptions.lock().await.retain(|_, sub| {
-        if !sub.is_valid() {
-            println!("invalid sub");
-            false
-        } else {
-            if sub.matches_topic(topic) {
-                sub.sender
-                    .try_send(MessageData {
-                        topic_name: topic.name.clone(),
-                        timestamp: timestamp_micros,
-                        r#type: r#type.clone(),
-                        data: data.to_owned(),
-                    })
-                    .is_ok()
-            } else {
-                true
+    // Decode once, every subscriber shares the same message
+    let message = match NtValue::from_frame(&r#type, data, frame) {
+        Ok(data) => Arc::new(MessageData {
+            topic_name,
+            timestamp: timestamp_micros,
+            r#type,
+            data,
+        }),
+        Err(err) => {
+            client.report(Diagnostic::InvalidMessage {
+                message: serde_json::json!({
+                    "topic": topic_name,
+                    "timestamp": timestamp_micros,
+                    "value": data.to_string(),
+                }),
+                error: err.to_string(),
+            });
+            return;
+        }
+    };
+
+    let mut closed = Vec::new();
+    {
+        let subscriptions = client.subscriptions.read();
+        for subuid in subuids.iter() {
+            let sub = match subscriptions.get(subuid) {
+                Some(sub) if sub.is_valid() => sub,
+                _ => {
+                    closed.push(*subuid);
+                    continue;
+                }
+            };
+
+            match sub.sender.send(Arc::clone(&message)) {
+                Ok(_) => {}
+                Err(SendError::Full) => {
+                    cfg_tracing! {
+                        tracing::warn!("Subscription {subuid} is full, dropping value for {}", message.topic_name);
+                    }
+                }
+                Err(SendError::Closed) => closed.push(*subuid),
             }
         }
-    });
+    }
+
+    // User has dropped these subs
+    if !closed.is_empty() {
+        {
+            let mut subscriptions = client.subscriptions.write();
+            for subuid in closed {
+                subscriptions.remove(&subuid);
+            }
+        }
+        client.matching_subscriptions.write().clear();
+    }
 }
 
 /// Upgrade the weak pointer or stop the task
@@ -653,27 +1376,20 @@ macro_rules! upgrade_client {
     };
 }
 
+const SOCKET_CHANNEL_SIZE: usize = 100;
+
 async fn setup_socket(
     client: Weak<InnerClient>,
     mut receiver: mpsc::Receiver<Message>,
-    panic_sender: oneshot::Sender<crate::Error>,
 ) -> Result<(), crate::Error> {
-    let mut request = format!(
-        "ws://{}/nt/{}-{}",
-        client.upgrade().unwrap().server_addr,
-        client.upgrade().unwrap().identity,
-        client.upgrade().unwrap().id
-    )
-    .into_client_request()?;
-    // Add sub-protocol header
-    request.headers_mut().append(
-        "Sec-WebSocket-Protocol",
-        HeaderValue::from_static("networktables.first.wpi.edu"),
-    );
+    let (request, connect_timeout) = {
+        let client = client.upgrade().ok_or(crate::Error::NotConnected)?;
+        (client.connect_request()?, client.config.connect_timeout)
+    };
     let uri = request.uri().clone();
 
     let (mut socket, _) = tokio::time::timeout(
-        Duration::from_millis(client.upgrade().unwrap().config.connect_timeout),
+        Duration::from_millis(connect_timeout),
         tokio_tungstenite::connect_async(request),
     )
     .await??;
@@ -683,7 +1399,13 @@ async fn setup_socket(
     }
 
     tokio::spawn(async move {
+        // Messages from the client waiting for the flush interval, and when it is over
+        let mut batch: Option<(FrameBatcher, tokio::time::Instant)> = None;
         loop {
+            let flush_deadline = batch
+                .as_ref()
+                .map(|(_, deadline)| *deadline)
+                .unwrap_or_else(tokio::time::Instant::now);
             let err: Result<(), crate::Error> = select! {
                 message = socket.try_next() => {
                     // Message from server
@@ -691,34 +1413,60 @@ async fn setup_socket(
                     match message {
                         Ok(Some(message)) => {
                             cfg_tracing! {tracing::trace!("Received Message: {:?}", message);}
-                            handle_message(upgrade_client!(client), message).await;
+                            handle_message(upgrade_client!(client), message);
                             Ok(())
                         },
                         Ok(None) => {
                             // If this happens we likely just need to reconnect
-                            handle_disconnect(Err::<(), _>(tokio_tungstenite::tungstenite::Error::AlreadyClosed), upgrade_client!(client), &mut socket).await.map_err(Into::into)
+                            handle_disconnect(Err::<(), _>(tokio_tungstenite::tungstenite::Error::AlreadyClosed), upgrade_client!(client), &mut socket).await
                         },
-                        Err(err) => handle_disconnect(Err::<(), _>(err), upgrade_client!(client), &mut socket).await.map_err(Into::into),
+                        Err(err) => handle_disconnect(Err::<(), _>(err), upgrade_client!(client), &mut socket).await,
                     }
                 },
                 message = receiver.recv() => {
                     // Message from client
                     if let Some(message) = message {
-                        handle_disconnect(
-                            socket.send(message).await,
-                            upgrade_client!(client),
-                            &mut socket
-                        ).await.map_err(Into::into)
+                        let client = upgrade_client!(client);
+                        let (batcher, _) = batch.get_or_insert_with(|| (
+                            FrameBatcher::new(client.config.batch_max_size),
+                            tokio::time::Instant::now() + Duration::from_millis(client.config.batch_flush_interval),
+                        ));
+                        if batch_messages(message, &mut receiver, batcher, &client.config, &client.buffer_pool) {
+                            let frames = batch.take().map(|(batcher, _)| batcher.finish()).unwrap_or_default();
+                            handle_disconnect(
+                                send_frames(&mut socket, frames).await,
+                                client,
+                                &mut socket
+                            ).await
+                        } else {
+                            Ok(())
+                        }
                     } else {
-                        // Other side of channel was dropped, end task
+                        // Other side of channel was dropped, send what's left & end task
+                        if let Some((batcher, _)) = batch.take() {
+                            send_frames(&mut socket, batcher.finish()).await.ok();
+                        }
                         cfg_tracing!{tracing::info!("Client dropped, ending socket handle task.");}
                         break;
                     }
                 },
+                _ = tokio::time::sleep_until(flush_deadline), if batch.is_some() => {
+                    // Flush interval is over
+                    let frames = batch.take().map(|(batcher, _)| batcher.finish()).unwrap_or_default();
+                    handle_disconnect(
+                        send_frames(&mut socket, frames).await,
+                        upgrade_client!(client),
+                        &mut socket
+                    ).await
+                },
             };
 
             if let Err(err) = err {
-                panic_sender.send(err).ok();
+                // Keep the error for every caller until the user restarts the connection
+                let client = upgrade_client!(client);
+                client
+                    .connection_state
+                    .send_replace(ConnectionState::Failed(Arc::new(err)));
                 break;
             }
         }
@@ -727,11 +1475,48 @@ async fn setup_socket(
     Ok(())
 }
 
+/// Adds queued messages from the client to a batch without waiting.
+/// Returns whether the batch should be sent now instead of after the flush interval.
+fn batch_messages(
+    first: Message,
+    receiver: &mut mpsc::Receiver<Message>,
+    batcher: &mut FrameBatcher,
+    config: &Config,
+    buffer_pool: &BufferPool,
+) -> bool {
+    let mut message = first;
+    loop {
+        let time_sync = is_time_sync(&message);
+        if let Some(buf) = batcher.push(message) {
+            buffer_pool.recycle(buf);
+        }
+
+        if time_sync || batcher.len() >= config.batch_max_size {
+            return true;
+        }
+        message = match receiver.try_recv() {
+            Ok(message) => message,
+            Err(_) => return config.batch_flush_interval == 0,
+        };
+    }
+}
+
+async fn send_frames(
+    socket: &mut WebSocket,
+    frames: Vec<Message>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    for frame in frames {
+        socket.feed(frame).await?;
+    }
+
+    socket.flush().await
+}
+
 async fn handle_disconnect<T>(
     result: Result<T, tokio_tungstenite::tungstenite::Error>,
     client: Arc<InnerClient>,
     socket: &mut WebSocket,
-) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+) -> Result<(), crate::Error> {
     // Reuse for dif branches
     let reconnect_client = client.clone();
 
@@ -739,6 +1524,9 @@ async fn handle_disconnect<T>(
         cfg_tracing! {
             tracing::info!("Disconnected from server, attempting to reconnect.");
         }
+        reconnect_client
+            .connection_state
+            .send_replace(ConnectionState::Reconnecting);
         (reconnect_client.config.on_disconnect)().await;
 
         loop {
@@ -747,17 +1535,7 @@ async fn handle_disconnect<T>(
             ))
             .await;
 
-            let mut request = format!(
-                "ws://{}/nt/{}-{}",
-                reconnect_client.server_addr, reconnect_client.identity, reconnect_client.id
-            )
-            .into_client_request()
-            .unwrap();
-            // Add sub-protocol header
-            request.headers_mut().append(
-                "Sec-WebSocket-Protocol",
-                HeaderValue::from_static("networktables.first.wpi.edu"),
-            );
+            let request = reconnect_client.connect_request()?;
 
             match tokio::time::timeout(
                 Duration::from_millis(reconnect_client.config.connect_timeout),
@@ -768,7 +1546,16 @@ async fn handle_disconnect<T>(
                 Ok(connect_result) => match connect_result {
                     Ok((new_socket, _)) => {
                         *socket = new_socket;
-                        reconnect_client.on_open().await;
+                        // Written straight to the socket, the queue can be full of values published while
+                        // disconnected and only this task empties it
+                        if let Ok(messages) = log_result(reconnect_client.open_messages()) {
+                            if send_frames(socket, messages.into()).await.is_err() {
+                                continue;
+                            }
+                        }
+                        reconnect_client
+                            .connection_state
+                            .send_replace(ConnectionState::Connected);
                         (reconnect_client.config.on_reconnect)().await;
 
                         cfg_tracing! {
@@ -791,26 +1578,503 @@ async fn handle_disconnect<T>(
                 reconnect().await
             } else {
                 cfg_tracing! {tracing::error!("Handle socket dying on {err:?}");}
-                Err(err)
+                Err(err.into())
             }
         }
     }
 }
 
-#[derive(Debug)]
-enum UnsignedIntOrNegativeOne {
-    NegativeOne,
-    UnsignedInt(u32),
+#[cfg(test)]
+impl Client {
+    /// Client without a socket task, the receiver gets the messages it would send to the server
+    pub(crate) fn unconnected() -> (Self, mpsc::Receiver<Message>) {
+        Self::unconnected_w_config(Config::default())
+    }
+
+    pub(crate) fn unconnected_w_config(config: Config) -> (Self, mpsc::Receiver<Message>) {
+        let (inner, socket_receiver, _) =
+            InnerClient::new(([127, 0, 0, 1], 5810).into(), config, None);
+        let client = Self {
+            inner: Arc::new(inner),
+        };
+        (client, socket_receiver)
+    }
 }
 
-impl UnsignedIntOrNegativeOne {
-    pub fn write_to_buf<W: rmp::encode::RmpWrite>(
-        &self,
-        wr: &mut W,
-    ) -> Result<(), rmp::encode::ValueWriteError<W::Error>> {
-        match self {
-            Self::NegativeOne => rmp::encode::write_i32(wr, -1),
-            Self::UnsignedInt(u_int) => rmp::encode::write_u32(wr, *u_int),
+#[cfg(test)]
+impl Subscription {
+    /// Subscription which isn't known to any client, values are sent through the receiver's sender
+    pub(crate) fn from_receiver(receiver: mpsc::Receiver<Arc<MessageData>>) -> Self {
+        let data = Arc::new(SubscriptionData {
+            subuid: 0,
+            topics: HashSet::new(),
+            options: None,
+        });
+        Self { data, receiver }
+    }
+}
+
+#[cfg(test)]
+impl MessageData {
+    /// Value as it would be sent to subscriptions
+    pub(crate) fn received(
+        topic_name: &str,
+        timestamp: u32,
+        r#type: Type,
+        data: NtValue,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            topic_name: topic_name.to_owned(),
+            timestamp,
+            r#type,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v4::{Clock, ManualClock};
+
+    /// Server time sent in time sync responses
+    const SERVER_TIME: u32 = 1_000_000;
+
+    fn client(clock: &ManualClock) -> InnerClient {
+        let config = Config {
+            clock: Arc::new(clock.clone()),
+            ..Default::default()
+        };
+        InnerClient::new(([127, 0, 0, 1], 5810).into(), config, None).0
+    }
+
+    /// Answers a time sync request sent now, with the server's time halfway through a round trip of `rtt` microseconds
+    fn sync(client: &InnerClient, clock: &ManualClock, server_time: u32, rtt: u32) -> Option<()> {
+        let sent = client.client_time();
+        clock.advance(Duration::from_micros(rtt.into()));
+        client.handle_new_timestamp(server_time, Some(sent.into()))
+    }
+
+    #[test]
+    fn client_time_follows_clock() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        assert_eq!(client.client_time(), 0);
+
+        clock.advance(Duration::from_micros(1500));
+        assert_eq!(client.client_time(), 1500);
+        // No sync yet, so server time is client time
+        assert_eq!(client.server_time(), 1500);
+    }
+
+    #[test]
+    fn handle_new_timestamp_sets_offset() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        clock.advance(Duration::from_millis(10));
+
+        assert_eq!(sync(&client, &clock, SERVER_TIME, 100), Some(()));
+        // The server sent its time halfway through the round trip
+        assert_eq!(client.server_time(), SERVER_TIME + 50);
+
+        clock.advance(Duration::from_micros(250));
+        assert_eq!(client.server_time(), SERVER_TIME + 300);
+
+        let stats = client.time_sync.lock().stats().unwrap();
+        assert_eq!(stats.rtt, Duration::from_micros(100));
+        assert_eq!(stats.samples, 1);
+    }
+
+    #[test]
+    fn handle_new_timestamp_ignores_responses_without_client_time() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        clock.advance(Duration::from_millis(1));
+
+        assert_eq!(client.handle_new_timestamp(SERVER_TIME, None), Some(()));
+        assert!(client.time_sync.lock().stats().is_none());
+        assert_eq!(client.server_time(), 1000);
+    }
+
+    #[test]
+    fn handle_new_timestamp_fails_when_client_time_wraps() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        assert_eq!(sync(&client, &clock, SERVER_TIME, 100), Some(()));
+
+        // Send just before client time wraps around & receive after
+        clock.advance(Duration::from_micros(u64::from(u32::MAX) - 300));
+        let server_time = client.server_time();
+        assert_eq!(sync(&client, &clock, server_time, 600), None);
+        assert_eq!(client.time_sync.lock().stats().unwrap().samples, 1);
+
+        // Restarting client time keeps the server time the same
+        let server_time = client.server_time();
+        client.rebase_time();
+        assert_eq!(client.client_time(), 0);
+        assert_eq!(client.server_time(), server_time);
+
+        // Samples taken after rebasing agree with the old ones
+        assert_eq!(
+            sync(&client, &clock, server_time.wrapping_add(50), 100),
+            Some(())
+        );
+        assert_eq!(client.server_time(), server_time.wrapping_add(100));
+    }
+
+    #[test]
+    fn server_time_wraps_around() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        assert_eq!(sync(&client, &clock, u32::MAX - 1000, 100), Some(()));
+        let before = client.server_time();
+
+        clock.advance(Duration::from_micros(2000));
+        assert_eq!(client.server_time(), before.wrapping_add(2000));
+        assert!(client.server_time() < before);
+    }
+
+    #[test]
+    fn reset_time_forgets_offset() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(sync(&client, &clock, SERVER_TIME, 100), Some(()));
+
+        client.reset_time();
+        assert_eq!(client.client_time(), 0);
+        assert_eq!(client.server_time(), 0);
+        assert!(client.time_sync.lock().stats().is_none());
+
+        // A response to a request from before the reset
+        assert_eq!(
+            client.handle_new_timestamp(SERVER_TIME, Some(3_000_000)),
+            None
+        );
+        assert!(client.time_sync.lock().stats().is_none());
+    }
+
+    #[test]
+    fn instant_at_matches_server_time_at() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        assert_eq!(sync(&client, &clock, SERVER_TIME, 100), Some(()));
+        clock.advance(Duration::from_secs(1));
+
+        let now = clock.now();
+        let earlier = now - Duration::from_millis(20);
+        let later = now + Duration::from_millis(20);
+        assert_eq!(
+            client.instant_at(client.server_time_at(earlier)),
+            Some(earlier)
+        );
+        assert_eq!(client.instant_at(client.server_time_at(later)), Some(later));
+        assert_eq!(client.instant_at(client.server_time()), Some(now));
+    }
+
+    #[test]
+    fn instant_at_after_client_time_wraps() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        assert_eq!(sync(&client, &clock, SERVER_TIME, 100), Some(()));
+
+        // Longer than client time can count in microseconds
+        clock.advance(Duration::from_secs(80 * 60));
+        let now = clock.now();
+        let earlier = now - Duration::from_secs(5);
+        assert_eq!(
+            client.instant_at(client.server_time_at(earlier)),
+            Some(earlier)
+        );
+        assert_eq!(client.instant_at(client.server_time()), Some(now));
+    }
+
+    #[test]
+    fn bad_message_keeps_rest_of_frame() {
+        let clock = ManualClock::new();
+        let client = Arc::new(client(&clock));
+        let mut diagnostics = client.diagnostics.subscribe();
+
+        let frame = r#"[
+            {"method": "announce", "params": {"name": "/a", "id": 1, "type": "int", "properties": {}}},
+            {"method": "announce", "params": {"name": "/b", "type": "int", "properties": {}}},
+            {"method": "newmethod", "params": {}},
+            {"method": "announce", "params": {"name": "/c", "id": 3, "type": "struct:Pose2d", "properties": {}}}
+        ]"#;
+        handle_message(client.clone(), Message::Text(frame.to_owned()));
+
+        {
+            let announced = client.announced_topics.read();
+            assert_eq!(announced.get_by_name("/a").map(|topic| topic.id), Some(1));
+            assert!(announced.get_by_name("/b").is_none());
+            assert_eq!(announced.get_by_name("/c").map(|topic| topic.id), Some(3));
         }
+
+        assert!(matches!(
+            diagnostics.try_recv(),
+            Ok(Diagnostic::InvalidMessage { .. })
+        ));
+        assert!(matches!(
+            diagnostics.try_recv(),
+            Ok(Diagnostic::UnknownMethod { .. })
+        ));
+        assert!(diagnostics.try_recv().is_err());
+
+        // A binary element which isn't a value update, followed by a time sync response
+        let mut frame = Vec::new();
+        rmp::encode::write_sint(&mut frame, 5).unwrap();
+        let client_time = i64::from(client.client_time());
+        encode::write_value_message(&mut frame, -1, SERVER_TIME, &Type::Int, client_time).unwrap();
+        handle_message(client.clone(), Message::Binary(frame));
+
+        assert!(matches!(
+            diagnostics.try_recv(),
+            Ok(Diagnostic::InvalidMessage { .. })
+        ));
+        assert!(client.time_sync.lock().stats().is_some());
+    }
+
+    fn value_message(id: i64) -> Message {
+        let mut buf = Vec::new();
+        encode::write_value_message(&mut buf, id, 0, &Type::Int, 1i64).unwrap();
+        Message::Binary(buf)
+    }
+
+    #[test]
+    fn batch_waits_for_flush_interval() {
+        let config = Config {
+            batch_flush_interval: 5,
+            ..Default::default()
+        };
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut batcher = FrameBatcher::new(config.batch_max_size);
+        let pool = BufferPool::new();
+
+        sender.try_send(value_message(2)).unwrap();
+        assert!(!batch_messages(
+            value_message(1),
+            &mut receiver,
+            &mut batcher,
+            &config,
+            &pool
+        ));
+        assert!(receiver.try_recv().is_err());
+
+        // Time sync requests are sent right away, after what's already batched
+        sender.try_send(value_message(3)).unwrap();
+        assert!(batch_messages(
+            value_message(-1),
+            &mut receiver,
+            &mut batcher,
+            &config,
+            &pool
+        ));
+        assert!(receiver.try_recv().is_ok());
+        assert_eq!(batcher.finish().len(), 1);
+    }
+
+    #[test]
+    fn batch_without_flush_interval_sends_queued_messages() {
+        let config = Config::default();
+        let (sender, mut receiver) = mpsc::channel(8);
+        let mut batcher = FrameBatcher::new(config.batch_max_size);
+
+        sender.try_send(value_message(2)).unwrap();
+        sender.try_send(Message::Text("[]".to_owned())).unwrap();
+        assert!(batch_messages(
+            value_message(1),
+            &mut receiver,
+            &mut batcher,
+            &config,
+            &BufferPool::new()
+        ));
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(batcher.finish().len(), 2);
+    }
+
+    fn announce(client: &Client, name: &str, id: i32, r#type: &str, pubuid: u32) {
+        let frame = format!(
+            r#"[{{"method": "announce", "params": {{"name": "{name}", "id": {id}, "type": "{type}", "pubuid": {pubuid}, "properties": {{}}}}}}]"#
+        );
+        handle_message(client.inner.clone(), Message::Text(frame));
+    }
+
+    fn is_type_conflict(result: Result<(), crate::Error>) -> bool {
+        matches!(
+            result,
+            Err(crate::Error::TypeConflict {
+                published: Type::Int,
+                announced: Type::Double,
+                ..
+            })
+        )
+    }
+
+    #[tokio::test]
+    async fn type_conflict_blocks_publishing() {
+        let (client, _socket_receiver) = Client::unconnected();
+        let mut diagnostics = client.diagnostics();
+        let topic = client.publish_topic("/a", Type::Int, None).await.unwrap();
+        let other = client.publish_topic("/b", Type::Int, None).await.unwrap();
+
+        announce(&client, "/a", 1, "double", topic.pubuid);
+        announce(&client, "/b", 2, "int", other.pubuid);
+        assert_eq!(client.type_conflict(&topic), Some(Type::Double));
+        assert_eq!(client.type_conflict(&other), None);
+        assert!(matches!(
+            diagnostics.try_recv(),
+            Ok(Diagnostic::TypeConflict { .. })
+        ));
+        assert!(diagnostics.try_recv().is_err());
+
+        assert!(is_type_conflict(client.publish_value(&topic, 1i64).await));
+        assert!(is_type_conflict(client.try_publish_value(&topic, 1i64)));
+        let mut batch = client.publish_batch();
+        assert!(is_type_conflict(batch.add(&topic, 1i64).map(|_| ())));
+        assert!(batch.add(&other, 1i64).is_ok());
+        assert_eq!(batch.len(), 1);
+        drop(batch);
+        assert!(client.try_publish_value(&other, 1i64).is_ok());
+
+        client.unpublish(topic.clone()).await.unwrap();
+        assert_eq!(client.type_conflict(&topic), None);
+        assert!(client
+            .inner
+            .check_type_conflict(topic.pubuid.into())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn cancelled_entry_unsubscribes() {
+        let (client, mut sent) = Client::unconnected();
+        // Leave room for the subscribe message only, so publishing waits
+        for _ in 0..SOCKET_CHANNEL_SIZE - 1 {
+            client
+                .inner
+                .try_send_message(Message::Text("[]".to_owned()))
+                .unwrap();
+        }
+        let entry = tokio::time::timeout(
+            Duration::from_millis(10),
+            client.entry("/a", Type::Int, None),
+        )
+        .await;
+        assert!(entry.is_err());
+
+        let mut methods = Vec::new();
+        tokio::task::yield_now().await;
+        while let Ok(Message::Text(text)) = sent.try_recv() {
+            tokio::task::yield_now().await;
+            let messages: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap();
+            methods.extend(
+                messages
+                    .iter()
+                    .filter_map(|message| message["method"].as_str().map(str::to_owned)),
+            );
+        }
+        assert_eq!(methods, ["subscribe", "unsubscribe"]);
+        assert!(client.inner.subscriptions.read().is_empty());
+    }
+
+    #[tokio::test]
+    async fn value_not_matching_type_is_reported() {
+        let (client, _sent) = Client::unconnected();
+        let mut diagnostics = client.diagnostics();
+        let mut subscription = client.subscribe(&["/a"]).await.unwrap();
+        handle_message(
+            client.inner.clone(),
+            Message::Text(
+                r#"[{"method": "announce", "params": {"name": "/a", "id": 1, "type": "int", "properties": {}}}]"#
+                    .to_owned(),
+            ),
+        );
+
+        let mut frame = Vec::new();
+        encode::write_value_message(&mut frame, 1, 10, &Type::String, "a").unwrap();
+        encode::write_value_message(&mut frame, 1, 20, &Type::Int, 5i64).unwrap();
+        handle_message(client.inner.clone(), Message::Binary(frame));
+
+        match diagnostics.try_recv() {
+            Ok(Diagnostic::InvalidMessage { message, .. }) => {
+                assert_eq!(message["topic"], "/a");
+                assert_eq!(message["timestamp"], 10);
+            }
+            diagnostic => panic!("expected an invalid message, got {diagnostic:?}"),
+        }
+        let message = subscription.next().await.unwrap();
+        assert_eq!(message.data, NtValue::Int(5));
+    }
+
+    #[tokio::test]
+    async fn type_conflict_with_same_wire_type() {
+        let (client, _sent) = Client::unconnected();
+        let cases = [
+            (Type::Raw, "struct:Pose2d"),
+            (Type::Struct("struct:Pose2d".to_owned()), "raw"),
+            (Type::Proto("proto:Pose2d".to_owned()), "struct:Pose2d"),
+            (Type::String, "json"),
+        ];
+        for (id, (published, announced)) in cases.into_iter().enumerate() {
+            let name = format!("/{id}");
+            let topic = client
+                .publish_topic(&name, published.clone(), None)
+                .await
+                .unwrap();
+            announce(&client, &name, id as i32, announced, topic.pubuid);
+
+            let announced = Type::from_str(announced).unwrap();
+            assert_eq!(published.as_u8(), announced.as_u8());
+            assert_eq!(client.type_conflict(&topic), Some(announced));
+        }
+
+        // The exact same custom type doesn't conflict
+        let r#type = Type::Struct("struct:Pose2d".to_owned());
+        let topic = client.publish_topic("/same", r#type, None).await.unwrap();
+        announce(&client, "/same", 10, "struct:Pose2d", topic.pubuid);
+        assert_eq!(client.type_conflict(&topic), None);
+    }
+
+    #[tokio::test]
+    async fn type_conflict_announced_before_publish_returns() {
+        let (client, mut sent) = Client::unconnected();
+        // Publishing waits for room in the queue
+        for _ in 0..SOCKET_CHANNEL_SIZE {
+            client
+                .inner
+                .try_send_message(Message::Text("[]".to_owned()))
+                .unwrap();
+        }
+        let publishing = {
+            let client = client.clone();
+            tokio::spawn(async move { client.publish_topic("/a", Type::Int, None).await })
+        };
+        tokio::task::yield_now().await;
+
+        let pubuid = *client
+            .inner
+            .client_published_topics
+            .lock()
+            .keys()
+            .next()
+            .unwrap();
+        announce(&client, "/a", 1, "double", pubuid);
+
+        while sent.try_recv().is_ok() {
+            tokio::task::yield_now().await;
+        }
+        let topic = publishing.await.unwrap().unwrap();
+        assert_eq!(client.type_conflict(&topic), Some(Type::Double));
+    }
+
+    #[tokio::test]
+    async fn failed_publish_is_forgotten() {
+        let (client, _sent) = Client::unconnected();
+        client
+            .inner
+            .connection_state
+            .send_replace(ConnectionState::Failed(Arc::new(crate::Error::Closed)));
+
+        assert!(client.publish_topic("/a", Type::Int, None).await.is_err());
+        assert!(client.inner.client_published_topics.lock().is_empty());
     }
 }
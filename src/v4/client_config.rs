@@ -1,17 +1,38 @@
-use std::{fmt::Debug, io};
+use std::{fmt::Debug, io, sync::Arc};
 
 use futures_util::future::BoxFuture;
 use tokio_tungstenite::tungstenite::error::ProtocolError;
 
-use super::Topic;
+use super::{
+    clock::{Clock, SystemClock},
+    Topic,
+};
 
 pub struct Config {
     /// milliseconds
     pub connect_timeout: u64,
     /// milliseconds
     pub disconnect_retry_interval: u64,
+    /// milliseconds to wait for more outgoing messages before sending them together.
+    /// With `0` only messages which are already queued are sent together.
+    /// Time sync requests are never held back.
+    pub batch_flush_interval: u64,
+    /// Max size in bytes of a websocket frame containing multiple messages
+    pub batch_max_size: usize,
+    /// milliseconds between time sync requests to the server, at least 1
+    pub time_sync_interval: u64,
+    /// Number of recent time sync samples to estimate the server time from
+    pub time_sync_samples: usize,
+    /// milliseconds to wait for the server to announce the topic of an [`Entry`](super::Entry)
+    /// before [`Entry::set_default`](super::Entry::set_default) decides there is no value
+    pub entry_sync_timeout: u64,
+    /// Source of time for timestamps & time sync, [`SystemClock`] by default
+    pub clock: Arc<dyn Clock>,
     pub should_reconnect: Box<dyn Fn(&tokio_tungstenite::tungstenite::Error) -> bool + Send + Sync>,
+    /// Called on a separate task in the order topics are announced, so it doesn't delay received values.
+    /// Announces queue up while it runs.
     pub on_announce: Box<dyn Fn(&Topic) -> BoxFuture<()> + Send + Sync>,
+    /// Called on the same task as `on_announce`
     pub on_un_announce: Box<dyn Fn(Option<Topic>) -> BoxFuture<'static, ()> + Send + Sync>,
     /// Called when there is an error with the websocket and `should_reconnect` returns true
     pub on_disconnect: Box<dyn Fn() -> BoxFuture<'static, ()> + Send + Sync>,
@@ -22,6 +43,13 @@ impl Debug for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Config")
             .field("connect_timeout", &self.connect_timeout)
+            .field("disconnect_retry_interval", &self.disconnect_retry_interval)
+            .field("batch_flush_interval", &self.batch_flush_interval)
+            .field("batch_max_size", &self.batch_max_size)
+            .field("time_sync_interval", &self.time_sync_interval)
+            .field("time_sync_samples", &self.time_sync_samples)
+            .field("entry_sync_timeout", &self.entry_sync_timeout)
+            .field("clock", &self.clock)
             .finish()
     }
 }
@@ -31,6 +59,12 @@ impl Default for Config {
         Self {
             connect_timeout: 500,
             disconnect_retry_interval: 1000,
+            batch_flush_interval: 0,
+            batch_max_size: 64 * 1024,
+            time_sync_interval: 5000,
+            time_sync_samples: 8,
+            entry_sync_timeout: 500,
+            clock: Arc::new(SystemClock),
             should_reconnect: Box::new(default_should_reconnect),
             on_announce: Box::new(|_| Box::pin(async {})),
             on_un_announce: Box::new(|_| Box::pin(async {})),
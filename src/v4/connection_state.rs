@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+/// State of a client's connection to the server
+#[derive(Debug, Clone)]
+pub enum ConnectionState {
+    Connected,
+    /// The connection was lost and the client is trying to reconnect
+    Reconnecting,
+    /// The connection was lost with an error that `should_reconnect` didn't allow reconnecting after.
+    /// Every call that needs the connection returns [`crate::Error::ConnectionFailed`] with this error until
+    /// [`Client::reconnect`](super::Client::reconnect) succeeds.
+    Failed(Arc<crate::Error>),
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, Self::Connected)
+    }
+
+    /// Returns the error which ended the connection
+    pub fn error(&self) -> Option<&Arc<crate::Error>> {
+        match self {
+            Self::Failed(err) => Some(err),
+            _ => None,
+        }
+    }
+}
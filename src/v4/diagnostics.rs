@@ -0,0 +1,101 @@
+/// Problems with messages sent by the server which didn't stop the client from working.
+///
+/// Received through [`Client::diagnostics`](super::Client::diagnostics).
+#[derive(Debug, Clone)]
+pub enum Diagnostic {
+    /// The server sent a message with a method this client doesn't know about,
+    /// likely from a newer version of the protocol
+    UnknownMethod {
+        method: String,
+        params: Option<serde_json::Value>,
+    },
+    /// The server sent a message with a known method which couldn't be parsed,
+    /// or a value which doesn't match its topic's type. Values are described as json since they are msgpack.
+    InvalidMessage {
+        message: serde_json::Value,
+        error: String,
+    },
+    /// The server sent a message which only clients are supposed to send
+    UnexpectedMethod { method: String },
+    /// The server announced a topic we published with a different type, because it already existed with that type.
+    /// Publishing values to the topic returns [`crate::Error::TypeConflict`] until it is unpublished.
+    TypeConflict {
+        topic: String,
+        published: super::Type,
+        announced: super::Type,
+    },
+}
+
+/// Methods which are part of the spec, so a message that fails to parse is invalid instead of unknown
+const KNOWN_METHODS: &[&str] = &[
+    "publish",
+    "unpublish",
+    "subscribe",
+    "unsubscribe",
+    "setproperties",
+    "announce",
+    "unannounce",
+    "properties",
+];
+
+impl Diagnostic {
+    /// Figures out why a message from the server failed to parse
+    pub(crate) fn from_parse_error(message: &serde_json::Value, error: serde_json::Error) -> Self {
+        match message.get("method").and_then(serde_json::Value::as_str) {
+            Some(method) if !KNOWN_METHODS.contains(&method) => Self::UnknownMethod {
+                method: method.to_owned(),
+                params: message.get("params").cloned(),
+            },
+            _ => Self::InvalidMessage {
+                message: message.clone(),
+                error: error.to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v4::NTMessage;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    fn diagnose(message: serde_json::Value) -> Diagnostic {
+        let error = NTMessage::deserialize(&message).unwrap_err();
+        Diagnostic::from_parse_error(&message, error)
+    }
+
+    #[test]
+    fn unknown_method() {
+        let params = json!({"name": "/topic"});
+        match diagnose(json!({"method": "newmethod", "params": params})) {
+            Diagnostic::UnknownMethod {
+                method,
+                params: Some(found),
+            } => {
+                assert_eq!(method, "newmethod");
+                assert_eq!(found, params);
+            }
+            diagnostic => panic!("expected an unknown method, got {diagnostic:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_known_method() {
+        // Missing the topic id
+        let message = json!({"method": "announce", "params": {"name": "/topic", "type": "int"}});
+        match diagnose(message.clone()) {
+            Diagnostic::InvalidMessage { message: found, .. } => assert_eq!(found, message),
+            diagnostic => panic!("expected an invalid message, got {diagnostic:?}"),
+        }
+    }
+
+    #[test]
+    fn missing_method() {
+        assert!(matches!(
+            diagnose(json!({"params": {}})),
+            Diagnostic::InvalidMessage { .. }
+        ));
+    }
+}
@@ -0,0 +1,410 @@
+use bytes::Bytes;
+
+use super::{NtValue, Type};
+
+/// Values which can be published without building a [`NtValue`].
+///
+/// Numbers are converted to the topic's type when it is a different numeric type,
+/// anything else that doesn't match returns [`crate::Error::TypeMismatch`].
+pub trait EncodeValue {
+    /// Writes the value as msgpack for a topic of the given type
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error>;
+}
+
+fn mismatch(r#type: &Type, found: &'static str) -> crate::Error {
+    crate::Error::TypeMismatch {
+        expected: r#type.clone(),
+        found,
+    }
+}
+
+fn is_raw(r#type: &Type) -> bool {
+    matches!(
+        r#type,
+        Type::Raw
+            | Type::Rpc
+            | Type::MsgPack
+            | Type::ProtoBuf
+            | Type::Struct(_)
+            | Type::StructArray(_)
+            | Type::Proto(_)
+            | Type::Other(_)
+    )
+}
+
+/// Writes a complete value update message: `[id, timestamp, type, value]`.
+/// `buf` is not cleared, so several messages can be written into one frame.
+pub fn write_value_message<T: EncodeValue>(
+    buf: &mut Vec<u8>,
+    id: i64,
+    timestamp: u32,
+    r#type: &Type,
+    value: T,
+) -> Result<(), crate::Error> {
+    rmp::encode::write_array_len(buf, 4)?;
+    rmp::encode::write_sint(buf, id)?;
+    rmp::encode::write_uint(buf, timestamp.into())?;
+    rmp::encode::write_uint(buf, r#type.as_u8().into())?;
+    value.encode(r#type, buf)
+}
+
+impl<T: EncodeValue + ?Sized> EncodeValue for &T {
+    #[inline]
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        (**self).encode(r#type, buf)
+    }
+}
+
+impl EncodeValue for bool {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        match r#type {
+            Type::Boolean => rmp::encode::write_bool(buf, *self)?,
+            _ => return Err(mismatch(r#type, "boolean")),
+        };
+        Ok(())
+    }
+}
+
+impl EncodeValue for f64 {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        match r#type {
+            Type::Double => rmp::encode::write_f64(buf, *self)?,
+            Type::Float => rmp::encode::write_f32(buf, *self as f32)?,
+            _ => return Err(mismatch(r#type, "double")),
+        };
+        Ok(())
+    }
+}
+
+impl EncodeValue for f32 {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        match r#type {
+            Type::Float => rmp::encode::write_f32(buf, *self)?,
+            Type::Double => rmp::encode::write_f64(buf, *self as f64)?,
+            _ => return Err(mismatch(r#type, "float")),
+        };
+        Ok(())
+    }
+}
+
+impl EncodeValue for i64 {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        match r#type {
+            Type::Int => {
+                rmp::encode::write_sint(buf, *self)?;
+            }
+            Type::Double => rmp::encode::write_f64(buf, *self as f64)?,
+            Type::Float => rmp::encode::write_f32(buf, *self as f32)?,
+            _ => return Err(mismatch(r#type, "int")),
+        };
+        Ok(())
+    }
+}
+
+impl EncodeValue for i32 {
+    #[inline]
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        i64::from(*self).encode(r#type, buf)
+    }
+}
+
+impl EncodeValue for str {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        match r#type {
+            Type::String | Type::Json => rmp::encode::write_str(buf, self)?,
+            _ => return Err(mismatch(r#type, "string")),
+        };
+        Ok(())
+    }
+}
+
+impl EncodeValue for String {
+    #[inline]
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        self.as_str().encode(r#type, buf)
+    }
+}
+
+impl EncodeValue for [u8] {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        if !is_raw(r#type) {
+            return Err(mismatch(r#type, "raw"));
+        }
+        rmp::encode::write_bin(buf, self)?;
+        Ok(())
+    }
+}
+
+impl EncodeValue for Bytes {
+    #[inline]
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        self[..].encode(r#type, buf)
+    }
+}
+
+impl EncodeValue for [bool] {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        if *r#type != Type::BooleanArray {
+            return Err(mismatch(r#type, "boolean[]"));
+        }
+        rmp::encode::write_array_len(buf, self.len() as u32)?;
+        for value in self {
+            rmp::encode::write_bool(buf, *value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes an array of numbers as doubles or floats
+macro_rules! encode_numeric_array {
+    ($self:ident, $type:ident, $buf:ident, $kind:literal, $($accepted:pat => $write:expr),*) => {{
+        match $type {
+            $(
+                $accepted => {
+                    rmp::encode::write_array_len($buf, $self.len() as u32)?;
+                    for value in $self {
+                        $write($buf, *value)?;
+                    }
+                }
+            )*
+            _ => return Err(mismatch($type, $kind)),
+        };
+        Ok(())
+    }};
+}
+
+impl EncodeValue for [f64] {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        encode_numeric_array!(self, r#type, buf, "double[]",
+            Type::DoubleArray => rmp::encode::write_f64,
+            Type::FloatArray => |buf: &mut Vec<u8>, value: f64| rmp::encode::write_f32(buf, value as f32)
+        )
+    }
+}
+
+impl EncodeValue for [f32] {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        encode_numeric_array!(self, r#type, buf, "float[]",
+            Type::FloatArray => rmp::encode::write_f32,
+            Type::DoubleArray => |buf: &mut Vec<u8>, value: f32| rmp::encode::write_f64(buf, value as f64)
+        )
+    }
+}
+
+impl EncodeValue for [i64] {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        encode_numeric_array!(self, r#type, buf, "int[]",
+            Type::IntArray => |buf: &mut Vec<u8>, value: i64| rmp::encode::write_sint(buf, value).map(|_| ()),
+            Type::DoubleArray => |buf: &mut Vec<u8>, value: i64| rmp::encode::write_f64(buf, value as f64),
+            Type::FloatArray => |buf: &mut Vec<u8>, value: i64| rmp::encode::write_f32(buf, value as f32)
+        )
+    }
+}
+
+impl EncodeValue for [String] {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        if *r#type != Type::StringArray {
+            return Err(mismatch(r#type, "string[]"));
+        }
+        rmp::encode::write_array_len(buf, self.len() as u32)?;
+        for value in self {
+            rmp::encode::write_str(buf, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl EncodeValue for [&str] {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        if *r#type != Type::StringArray {
+            return Err(mismatch(r#type, "string[]"));
+        }
+        rmp::encode::write_array_len(buf, self.len() as u32)?;
+        for value in self {
+            rmp::encode::write_str(buf, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> EncodeValue for Vec<T>
+where
+    [T]: EncodeValue,
+{
+    #[inline]
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        self.as_slice().encode(r#type, buf)
+    }
+}
+
+impl<T, const N: usize> EncodeValue for [T; N]
+where
+    [T]: EncodeValue,
+{
+    #[inline]
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        self.as_slice().encode(r#type, buf)
+    }
+}
+
+impl EncodeValue for NtValue {
+    fn encode(&self, r#type: &Type, buf: &mut Vec<u8>) -> Result<(), crate::Error> {
+        match self {
+            Self::Boolean(value) => value.encode(r#type, buf),
+            Self::Double(value) => value.encode(r#type, buf),
+            Self::Int(value) => value.encode(r#type, buf),
+            Self::Float(value) => value.encode(r#type, buf),
+            Self::String(value) => value.encode(r#type, buf),
+            Self::Raw(value) => value.encode(r#type, buf),
+            Self::BooleanArray(values) => values.encode(r#type, buf),
+            Self::DoubleArray(values) => values.encode(r#type, buf),
+            Self::IntArray(values) => values.encode(r#type, buf),
+            Self::FloatArray(values) => values.encode(r#type, buf),
+            Self::StringArray(values) => values.encode(r#type, buf),
+        }
+    }
+}
+
+/// Reusable buffers for encoding outgoing messages.
+///
+/// The socket task copies every message into the frame it sends & recycles the message's buffer,
+/// so encoding a value doesn't allocate once warmed up. Only the frames are allocated, once per frame.
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    buffers: parking_lot::Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// Buffers kept at most, any more are freed
+    const MAX_BUFFERS: usize = 256;
+    /// Buffers which grew larger than this (e.g. for big raw values) are freed instead of kept
+    const MAX_CAPACITY: usize = 16 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns an empty buffer
+    pub fn take(&self) -> Vec<u8> {
+        self.buffers
+            .lock()
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(64))
+    }
+
+    /// Gives a buffer back to be reused
+    pub fn recycle(&self, mut buf: Vec<u8>) {
+        if buf.capacity() > Self::MAX_CAPACITY {
+            return;
+        }
+
+        buf.clear();
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < Self::MAX_BUFFERS {
+            buffers.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v4::value::test_util::{found, read_value};
+    use rmpv::Value;
+
+    /// Writes a value message & decodes it back into `[id, timestamp, type, value]`
+    fn message<T: EncodeValue>(r#type: &Type, value: T) -> Result<Vec<Value>, crate::Error> {
+        let mut buf = Vec::new();
+        write_value_message(&mut buf, 7, 1234, r#type, value)?;
+
+        match read_value(&buf) {
+            Value::Array(message) => Ok(message),
+            message => panic!("expected an array, got {message:?}"),
+        }
+    }
+
+    #[test]
+    fn int_on_double_topic() {
+        assert_eq!(
+            message(&Type::Double, 3i64).unwrap(),
+            [
+                Value::from(7),
+                Value::from(1234),
+                Value::from(1),
+                Value::F64(3.0)
+            ]
+        );
+        assert_eq!(message(&Type::Double, 3i32).unwrap()[3], Value::F64(3.0));
+    }
+
+    #[test]
+    fn double_on_float_topic() {
+        assert_eq!(
+            message(&Type::Float, 0.5f64).unwrap(),
+            [
+                Value::from(7),
+                Value::from(1234),
+                Value::from(3),
+                Value::F32(0.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn ints_on_double_array_topic() {
+        let values: &[i64] = &[1, -2];
+        assert_eq!(
+            message(&Type::DoubleArray, values).unwrap(),
+            [
+                Value::from(7),
+                Value::from(1234),
+                Value::from(17),
+                Value::Array(vec![Value::F64(1.0), Value::F64(-2.0)])
+            ]
+        );
+    }
+
+    #[test]
+    fn mismatches() {
+        assert_eq!(found(message(&Type::DoubleArray, "a")), "string");
+        assert_eq!(found(message(&Type::DoubleArray, ["a"])), "string[]");
+        assert_eq!(found(message(&Type::Int, &[1u8, 2][..])), "raw");
+        assert_eq!(found(message(&Type::Int, 0.5f64)), "double");
+        assert_eq!(found(message(&Type::String, true)), "boolean");
+    }
+
+    #[test]
+    fn round_trip() {
+        let values = [
+            (Type::Boolean, NtValue::Boolean(true)),
+            (Type::Double, NtValue::Double(1.5)),
+            (Type::Int, NtValue::Int(-40)),
+            (Type::Float, NtValue::Float(0.25)),
+            (Type::Json, NtValue::String("{}".to_owned())),
+            (
+                Type::Struct("struct:Pose2d".to_owned()),
+                NtValue::Raw(Bytes::from_static(&[1, 2, 3])),
+            ),
+            (Type::IntArray, NtValue::IntArray(vec![1, i64::MIN])),
+            (Type::FloatArray, NtValue::FloatArray(vec![0.5])),
+            (
+                Type::StringArray,
+                NtValue::StringArray(vec!["a".to_owned(), String::new()]),
+            ),
+        ];
+
+        for (r#type, value) in values {
+            let mut buf = Vec::new();
+            write_value_message(&mut buf, 1, 0, &r#type, &value).unwrap();
+            let frame = Bytes::from(buf);
+            let mut remaining: &[u8] = &frame;
+            let decoded = match rmpv::decode::read_value_ref(&mut remaining).unwrap() {
+                rmpv::ValueRef::Array(message) => {
+                    NtValue::from_frame(&r#type, &message[3], &frame).unwrap()
+                }
+                message => panic!("expected an array, got {message:?}"),
+            };
+            assert_eq!(decoded, value);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::{sync::Arc, time::Duration};
+
+use super::{client::spawn_cleanup, Client, MessageData, NtValue, PublishedTopic, Subscription};
+
+/// A combined publisher & subscriber for a single topic, similar to WPILib's `NetworkTableEntry`.
+///
+/// Created with [`Client::entry`]. The topic is unpublished when the entry is dropped.
+#[derive(Debug)]
+pub struct Entry {
+    pub(crate) client: Client,
+    pub(crate) topic: PublishedTopic,
+    pub(crate) subscription: Option<Subscription>,
+    pub(crate) latest: Option<Arc<MessageData>>,
+    /// How long to wait for the server to announce the topic to us as its publisher
+    pub(crate) sync_timeout: Duration,
+}
+
+impl Entry {
+    /// Name of the topic this entry is for
+    pub fn name(&self) -> &str {
+        &self.topic.name
+    }
+
+    pub fn published_topic(&self) -> &PublishedTopic {
+        &self.topic
+    }
+
+    /// Takes all values received since the last call and keeps the newest one
+    fn poll_subscription(&mut self) {
+        if let Some(subscription) = self.subscription.as_mut() {
+            while let Ok(message) = subscription.receiver.try_recv() {
+                self.latest = Some(message);
+            }
+        }
+    }
+
+    /// Current value of the topic, or `None` if nobody has published a value yet
+    pub fn get(&mut self) -> Option<&NtValue> {
+        self.get_message().map(|message| &message.data)
+    }
+
+    /// Most recent value of the topic along with its timestamp
+    pub fn get_message(&mut self) -> Option<&MessageData> {
+        self.poll_subscription();
+        self.latest.as_deref()
+    }
+
+    /// Publishes a new value for this topic
+    pub async fn set(&mut self, value: impl Into<NtValue>) -> Result<(), crate::Error> {
+        let value = value.into();
+        let topic = &self.topic;
+        let timestamp = self.client.server_time();
+        self.client
+            .publish_value_w_timestamp(topic, timestamp, &value)
+            .await?;
+
+        let message = Arc::new(MessageData {
+            topic_name: topic.name.clone(),
+            timestamp,
+            r#type: topic.r#type.clone(),
+            data: value,
+        });
+        self.poll_subscription();
+        self.latest = Some(message);
+
+        Ok(())
+    }
+
+    /// Publishes `value` only if the topic does not have a value yet.
+    /// Waits for the server to announce the topic to us as its publisher before deciding,
+    /// which it does after sending the current value for our subscription.
+    ///
+    /// Returns `true` if the default was published.
+    pub async fn set_default(&mut self, value: impl Into<NtValue>) -> Result<bool, crate::Error> {
+        self.poll_subscription();
+
+        if self.latest.is_none() {
+            let pubuid = i32::try_from(self.topic.pubuid).ok();
+            let announced = self
+                .client
+                .wait_for_announce(&self.topic.name, |topic| topic.pubuid == pubuid);
+            tokio::time::timeout(self.sync_timeout, announced)
+                .await
+                .ok();
+            self.poll_subscription();
+        }
+
+        if self.latest.is_some() {
+            return Ok(false);
+        }
+
+        self.set(value).await?;
+        Ok(true)
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let subscription = match self.subscription.take() {
+            Some(subscription) => subscription,
+            None => return,
+        };
+
+        let client = self.client.clone();
+        let topic = self.topic.clone();
+        spawn_cleanup(async move {
+            client.unpublish(topic).await.ok();
+            client.unsubscribe(subscription).await.ok();
+        });
+    }
+}
@@ -0,0 +1,260 @@
+use tokio_tungstenite::tungstenite::Message;
+
+/// Combines outgoing messages into as few websocket frames as possible.
+///
+/// The spec allows multiple msgpack messages in one binary frame and multiple json messages in one text frame.
+/// Message order is kept, so a batch is finished whenever the kind of message changes.
+#[derive(Debug)]
+pub(crate) struct FrameBatcher {
+    max_size: usize,
+    pending: Option<Message>,
+    frames: Vec<Message>,
+    /// Size of `frames`
+    frames_len: usize,
+}
+
+impl FrameBatcher {
+    pub(crate) fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            pending: None,
+            frames: Vec::new(),
+            frames_len: 0,
+        }
+    }
+
+    /// Size of the finished frames & the frame being built
+    pub(crate) fn len(&self) -> usize {
+        self.frames_len + self.pending.as_ref().map(Message::len).unwrap_or(0)
+    }
+
+    fn finish_pending(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.frames_len += pending.len();
+            self.frames.push(pending);
+        }
+    }
+
+    /// Returns the buffer of a binary message after copying it into a frame, so it can be reused.
+    /// Frames are consumed when they are sent, so the buffers of messages never become frames.
+    pub(crate) fn push(&mut self, message: Message) -> Option<Vec<u8>> {
+        match (self.pending.as_mut(), message) {
+            (Some(Message::Binary(pending)), Message::Binary(message))
+                if pending.len() + message.len() <= self.max_size =>
+            {
+                pending.extend_from_slice(&message);
+                return Some(message);
+            }
+            (Some(Message::Text(pending)), Message::Text(message))
+                if pending.len() + message.len() <= self.max_size
+                    && json_array_elements(pending).is_some()
+                    && json_array_elements(&message).is_some() =>
+            {
+                append_json_array(pending, &message);
+            }
+            (_, Message::Binary(message)) => {
+                self.finish_pending();
+                self.pending = Some(Message::Binary(message.clone()));
+                return Some(message);
+            }
+            (_, message @ Message::Text(_)) => {
+                self.finish_pending();
+                self.pending = Some(message);
+            }
+            (_, message) => {
+                // Control frames can't be combined
+                self.finish_pending();
+                self.frames_len += message.len();
+                self.frames.push(message);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the frames to send in order
+    pub(crate) fn finish(mut self) -> Vec<Message> {
+        self.finish_pending();
+        self.frames
+    }
+}
+
+/// Time sync requests are value updates with id -1. They carry the client time from when they were queued,
+/// so they are sent right away instead of waiting in a batch.
+pub(crate) fn is_time_sync(message: &Message) -> bool {
+    match message {
+        Message::Binary(buf) => {
+            let mut buf = buf.as_slice();
+            rmp::decode::read_array_len(&mut buf).is_ok()
+                && rmp::decode::read_int::<i64, _>(&mut buf).is_ok_and(|id| id == -1)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the text between the brackets of a json array
+fn json_array_elements(array: &str) -> Option<&str> {
+    array
+        .trim()
+        .strip_prefix('[')
+        .and_then(|array| array.strip_suffix(']'))
+        .map(str::trim)
+}
+
+/// Appends the elements of the json array `other` to the json array `array`, both must be arrays
+fn append_json_array(array: &mut String, other: &str) {
+    let other_elements = match json_array_elements(other) {
+        Some(elements) if !elements.is_empty() => elements,
+        _ => return,
+    };
+
+    let has_elements = json_array_elements(array).is_some_and(|elements| !elements.is_empty());
+    array.truncate(array.trim_end().len() - 1);
+    if has_elements {
+        array.push(',');
+    }
+    array.push_str(other_elements);
+    array.push(']');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Message {
+        Message::Text(text.to_owned())
+    }
+
+    fn binary(bytes: &[u8]) -> Message {
+        Message::Binary(bytes.to_vec())
+    }
+
+    fn batch(max_size: usize, messages: Vec<Message>) -> Vec<Message> {
+        let mut batcher = FrameBatcher::new(max_size);
+        for message in messages {
+            batcher.push(message);
+        }
+        batcher.finish()
+    }
+
+    #[test]
+    fn combines_binary_messages() {
+        let mut batcher = FrameBatcher::new(1024);
+        assert_eq!(batcher.push(binary(&[1, 2])), Some(vec![1, 2]));
+        assert_eq!(batcher.push(binary(&[3])), Some(vec![3]));
+        assert_eq!(batcher.push(text("[1]")), None);
+        assert_eq!(batcher.len(), 6);
+        assert_eq!(batcher.finish(), vec![binary(&[1, 2, 3]), text("[1]")]);
+    }
+
+    #[test]
+    fn combines_json_arrays() {
+        let frames = batch(
+            1024,
+            vec![text(r#"[{"a":1}]"#), text(r#"[{"b":2},{"c":3}]"#)],
+        );
+        assert_eq!(frames, vec![text(r#"[{"a":1},{"b":2},{"c":3}]"#)]);
+    }
+
+    #[test]
+    fn combines_empty_json_arrays() {
+        assert_eq!(
+            batch(1024, vec![text("[]"), text("[1]")]),
+            vec![text("[1]")]
+        );
+        assert_eq!(
+            batch(1024, vec![text("[1]"), text("[]")]),
+            vec![text("[1]")]
+        );
+        assert_eq!(
+            batch(1024, vec![text("[ ]"), text("[]")]),
+            vec![text("[ ]")]
+        );
+    }
+
+    #[test]
+    fn combines_json_arrays_with_whitespace() {
+        let frames = batch(1024, vec![text(" [1] \n"), text("\t[ 2 ] ")]);
+        assert_eq!(frames, vec![text(" [1,2]")]);
+
+        let combined = match &frames[0] {
+            Message::Text(combined) => combined,
+            _ => unreachable!(),
+        };
+        let parsed: Vec<i32> = serde_json::from_str(combined).unwrap();
+        assert_eq!(parsed, vec![1, 2]);
+    }
+
+    #[test]
+    fn keeps_text_which_isnt_an_array() {
+        let frames = batch(1024, vec![text("[1]"), text(r#"{"a":1}"#)]);
+        assert_eq!(frames, vec![text("[1]"), text(r#"{"a":1}"#)]);
+    }
+
+    #[test]
+    fn respects_max_size() {
+        let frames = batch(4, vec![binary(&[1, 2]), binary(&[3, 4]), binary(&[5])]);
+        assert_eq!(frames, vec![binary(&[1, 2, 3, 4]), binary(&[5])]);
+
+        let frames = batch(5, vec![text("[1]"), text("[2]")]);
+        assert_eq!(frames, vec![text("[1]"), text("[2]")]);
+    }
+
+    #[test]
+    fn keeps_order_of_text_and_binary() {
+        let frames = batch(
+            1024,
+            vec![
+                binary(&[1]),
+                binary(&[2]),
+                text("[1]"),
+                binary(&[3]),
+                text("[2]"),
+                text("[3]"),
+                Message::Ping(vec![]),
+                binary(&[4]),
+            ],
+        );
+        assert_eq!(
+            frames,
+            vec![
+                binary(&[1, 2]),
+                text("[1]"),
+                binary(&[3]),
+                text("[2,3]"),
+                Message::Ping(vec![]),
+                binary(&[4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn len_counts_every_frame() {
+        let mut batcher = FrameBatcher::new(1024);
+        batcher.push(binary(&[1, 2]));
+        batcher.push(text("[1]"));
+        batcher.push(text("[2]"));
+        batcher.push(Message::Ping(vec![0]));
+        batcher.push(binary(&[3]));
+
+        let len = batcher.len();
+        assert_eq!(
+            len,
+            batcher.finish().iter().map(Message::len).sum::<usize>()
+        );
+        assert_eq!(len, 2 + 5 + 1 + 1);
+    }
+
+    #[test]
+    fn detects_time_sync() {
+        let message = |id, r#type| {
+            let mut buf = Vec::new();
+            crate::v4::encode::write_value_message(&mut buf, id, 0, &r#type, 5i64).unwrap();
+            Message::Binary(buf)
+        };
+        assert!(is_time_sync(&message(-1, crate::v4::Type::Int)));
+        assert!(!is_time_sync(&message(1, crate::v4::Type::Int)));
+        assert!(!is_time_sync(&text("[-1]")));
+        assert!(!is_time_sync(&binary(&[])));
+    }
+}
@@ -0,0 +1,447 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use super::{time_sync::time_diff, MessageData, NtValue, Subscription};
+
+/// How values of the other subscriptions of a [`Join`] are matched to the reference value's timestamp
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinPolicy {
+    /// Only values with the same timestamp
+    Exact,
+    /// The value closest in time, if it is within `tolerance`
+    Nearest { tolerance: Duration },
+    /// The newest value at or before the reference value, if it is at most `max_age` older
+    LatestBefore { max_age: Duration },
+    /// Linearly interpolates numeric values (and numeric arrays of equal length) between the values
+    /// before & after the reference value, both have to be within `tolerance`.
+    /// Other values are matched like [`JoinPolicy::Nearest`].
+    Interpolate { tolerance: Duration },
+}
+
+/// Values of every subscription of a [`Join`] aligned to the timestamp of the reference value
+#[derive(Debug, Clone)]
+pub struct JoinedSample {
+    pub timestamp: u32,
+    pub reference: Arc<MessageData>,
+    /// In the order the subscriptions were given to [`Join::new`]
+    pub others: Vec<Arc<MessageData>>,
+}
+
+#[derive(Debug)]
+struct Input {
+    subscription: Subscription,
+    // Oldest first
+    buffer: VecDeque<Arc<MessageData>>,
+    closed: bool,
+}
+
+/// Pairs every value of a reference subscription with the values of other subscriptions nearest in server time.
+///
+/// Values are assumed to arrive in timestamp order for each topic.
+/// Reference values without a match for every other subscription are skipped.
+#[derive(Debug)]
+pub struct Join {
+    reference: Subscription,
+    /// Reference value being matched, kept if [`Join::next`] is cancelled
+    pending: Option<Arc<MessageData>>,
+    others: Vec<Input>,
+    policy: JoinPolicy,
+    max_buffered: usize,
+    max_wait: Duration,
+}
+
+impl Join {
+    /// Values of `others` are kept for at most [`Self::with_max_buffered`] values each
+    pub fn new(reference: Subscription, others: Vec<Subscription>, policy: JoinPolicy) -> Self {
+        Self {
+            reference,
+            pending: None,
+            others: others
+                .into_iter()
+                .map(|subscription| Input {
+                    subscription,
+                    buffer: VecDeque::new(),
+                    closed: false,
+                })
+                .collect(),
+            policy,
+            max_buffered: 64,
+            max_wait: Duration::from_millis(100),
+        }
+    }
+
+    /// Max number of values kept per subscription while waiting for reference values, 64 by default
+    pub fn with_max_buffered(mut self, max_buffered: usize) -> Self {
+        self.max_buffered = max_buffered.max(1);
+        self
+    }
+
+    /// How long to wait for values after a reference value before matching with what was received, 100ms by default.
+    /// [`Duration::MAX`] waits until every value arrived.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Waits for the next reference value which every other subscription has a match for.
+    ///
+    /// Cancel safe, a reference value which was being matched is matched again by the next call.
+    /// Returns `None` if the client was dropped.
+    pub async fn next(&mut self) -> Option<JoinedSample> {
+        loop {
+            let reference = match &self.pending {
+                Some(reference) => reference.clone(),
+                None => self.pending.insert(self.reference.next().await?).clone(),
+            };
+            let timestamp = reference.timestamp;
+
+            self.receive_until(timestamp).await;
+            let others = self
+                .others
+                .iter()
+                .map(|input| self.find_match(input, timestamp))
+                .collect::<Option<Vec<_>>>();
+            self.prune(timestamp);
+            self.pending = None;
+
+            if let Some(others) = others {
+                return Some(JoinedSample {
+                    timestamp,
+                    reference,
+                    others,
+                });
+            }
+        }
+    }
+
+    fn push(max_buffered: usize, input: &mut Input, message: Arc<MessageData>) {
+        if input.buffer.len() == max_buffered {
+            input.buffer.pop_front();
+        }
+        input.buffer.push_back(message);
+    }
+
+    /// Receives values until every other subscription has one at or after `timestamp`, or `max_wait` has passed
+    async fn receive_until(&mut self, timestamp: u32) {
+        let needs_later = !matches!(self.policy, JoinPolicy::LatestBefore { .. });
+        // `None` if the wait is too long to represent, e.g. `Duration::MAX`, which waits forever
+        let deadline = tokio::time::Instant::now().checked_add(self.max_wait);
+
+        for input in &mut self.others {
+            while let Ok(message) = input.subscription.receiver.try_recv() {
+                Self::push(self.max_buffered, input, message);
+            }
+
+            let has_later = |input: &Input| {
+                input
+                    .buffer
+                    .back()
+                    .is_some_and(|latest| time_diff(latest.timestamp, timestamp) >= 0)
+            };
+            while needs_later && !input.closed && !has_later(input) {
+                let received = match deadline {
+                    Some(deadline) => {
+                        tokio::time::timeout_at(deadline, input.subscription.next()).await
+                    }
+                    None => Ok(input.subscription.next().await),
+                };
+                match received {
+                    Ok(Some(message)) => Self::push(self.max_buffered, input, message),
+                    Ok(None) => input.closed = true,
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    fn find_match(&self, input: &Input, timestamp: u32) -> Option<Arc<MessageData>> {
+        let before = input
+            .buffer
+            .iter()
+            .rev()
+            .find(|message| time_diff(message.timestamp, timestamp) <= 0);
+        let after = input
+            .buffer
+            .iter()
+            .find(|message| time_diff(message.timestamp, timestamp) >= 0);
+        let within = |message: &&Arc<MessageData>, tolerance: Duration| {
+            time_diff(message.timestamp, timestamp).unsigned_abs() <= tolerance.as_micros() as u64
+        };
+        let nearest = |tolerance: Duration| {
+            [before, after]
+                .into_iter()
+                .flatten()
+                .filter(|message| within(message, tolerance))
+                .min_by_key(|message| time_diff(message.timestamp, timestamp).unsigned_abs())
+                .cloned()
+        };
+
+        match self.policy {
+            JoinPolicy::Exact => after
+                .filter(|message| message.timestamp == timestamp)
+                .cloned(),
+            JoinPolicy::Nearest { tolerance } => nearest(tolerance),
+            JoinPolicy::LatestBefore { max_age } => {
+                before.filter(|message| within(message, max_age)).cloned()
+            }
+            JoinPolicy::Interpolate { tolerance } => match (before, after) {
+                (Some(before), Some(after))
+                    if within(&before, tolerance) && within(&after, tolerance) =>
+                {
+                    interpolate(before, after, timestamp).or_else(|| nearest(tolerance))
+                }
+                _ => nearest(tolerance),
+            },
+        }
+    }
+
+    /// Drops values which can't match any later reference value, keeping the newest one before `timestamp`
+    fn prune(&mut self, timestamp: u32) {
+        for input in &mut self.others {
+            while input.buffer.len() > 1 && time_diff(input.buffer[1].timestamp, timestamp) <= 0 {
+                input.buffer.pop_front();
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> (Subscription, Vec<Subscription>) {
+        (
+            self.reference,
+            self.others
+                .into_iter()
+                .map(|input| input.subscription)
+                .collect(),
+        )
+    }
+}
+
+/// Value between `before` & `after` at `timestamp`, `None` if the values aren't numeric
+fn interpolate(
+    before: &Arc<MessageData>,
+    after: &Arc<MessageData>,
+    timestamp: u32,
+) -> Option<Arc<MessageData>> {
+    let span = time_diff(after.timestamp, before.timestamp);
+    if span == 0 {
+        return Some(before.clone());
+    }
+    let t = time_diff(timestamp, before.timestamp) as f64 / span as f64;
+    let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+    let data = match (&before.data, &after.data) {
+        (NtValue::Double(a), NtValue::Double(b)) => NtValue::Double(lerp(*a, *b)),
+        (NtValue::Float(a), NtValue::Float(b)) => NtValue::Float(lerp(*a as f64, *b as f64) as f32),
+        (NtValue::Int(a), NtValue::Int(b)) => {
+            NtValue::Int(lerp(*a as f64, *b as f64).round() as i64)
+        }
+        (NtValue::DoubleArray(a), NtValue::DoubleArray(b)) if a.len() == b.len() => {
+            NtValue::DoubleArray(a.iter().zip(b).map(|(a, b)| lerp(*a, *b)).collect())
+        }
+        (NtValue::FloatArray(a), NtValue::FloatArray(b)) if a.len() == b.len() => {
+            NtValue::FloatArray(
+                a.iter()
+                    .zip(b)
+                    .map(|(a, b)| lerp(*a as f64, *b as f64) as f32)
+                    .collect(),
+            )
+        }
+        (NtValue::IntArray(a), NtValue::IntArray(b)) if a.len() == b.len() => NtValue::IntArray(
+            a.iter()
+                .zip(b)
+                .map(|(a, b)| lerp(*a as f64, *b as f64).round() as i64)
+                .collect(),
+        ),
+        _ => return None,
+    };
+
+    Some(Arc::new(MessageData {
+        topic_name: before.topic_name.clone(),
+        timestamp,
+        r#type: before.r#type.clone(),
+        data,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::v4::Type;
+
+    fn subscription() -> (mpsc::Sender<Arc<MessageData>>, Subscription) {
+        let (sender, receiver) = mpsc::channel(64);
+        (sender, Subscription::from_receiver(receiver))
+    }
+
+    fn message(timestamp: u32, data: NtValue) -> Arc<MessageData> {
+        MessageData::received("/test", timestamp, Type::Double, data)
+    }
+
+    /// Join of one other subscription where every value has already been received
+    async fn join(
+        policy: JoinPolicy,
+        reference: &[u32],
+        other: &[(u32, NtValue)],
+    ) -> Vec<(u32, u32, NtValue)> {
+        let (reference_sender, reference_subscription) = subscription();
+        let (other_sender, other_subscription) = subscription();
+        for timestamp in reference {
+            reference_sender
+                .send(message(*timestamp, NtValue::Double(0.0)))
+                .await
+                .unwrap();
+        }
+        for (timestamp, data) in other {
+            other_sender
+                .send(message(*timestamp, data.clone()))
+                .await
+                .unwrap();
+        }
+        drop((reference_sender, other_sender));
+
+        let mut join = Join::new(reference_subscription, vec![other_subscription], policy);
+        let mut samples = Vec::new();
+        while let Some(sample) = join.next().await {
+            let other = &sample.others[0];
+            samples.push((sample.timestamp, other.timestamp, other.data.clone()));
+        }
+        samples
+    }
+
+    fn doubles(values: &[(u32, f64)]) -> Vec<(u32, NtValue)> {
+        values
+            .iter()
+            .map(|(timestamp, value)| (*timestamp, NtValue::Double(*value)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn exact() {
+        let samples = join(
+            JoinPolicy::Exact,
+            &[100, 200, 300],
+            &doubles(&[(100, 1.0), (150, 2.0), (300, 3.0)]),
+        )
+        .await;
+        assert_eq!(
+            samples,
+            vec![
+                (100, 100, NtValue::Double(1.0)),
+                (300, 300, NtValue::Double(3.0))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn nearest() {
+        let other = doubles(&[(90, 1.0), (130, 2.0), (500, 3.0)]);
+        let tolerance = Duration::from_micros(20);
+        let samples = join(JoinPolicy::Nearest { tolerance }, &[100, 120, 300], &other).await;
+        assert_eq!(
+            samples,
+            vec![
+                (100, 90, NtValue::Double(1.0)),
+                (120, 130, NtValue::Double(2.0))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn latest_before() {
+        let other = doubles(&[(90, 1.0), (105, 2.0)]);
+        let max_age = Duration::from_micros(20);
+        let samples = join(JoinPolicy::LatestBefore { max_age }, &[100, 200], &other).await;
+        assert_eq!(samples, vec![(100, 90, NtValue::Double(1.0))]);
+    }
+
+    #[tokio::test]
+    async fn interpolates_numbers() {
+        let other = doubles(&[(0, 0.0), (200, 10.0)]);
+        let tolerance = Duration::from_micros(500);
+        let samples = join(JoinPolicy::Interpolate { tolerance }, &[50], &other).await;
+        assert_eq!(samples, vec![(50, 50, NtValue::Double(2.5))]);
+
+        let other = vec![
+            (0, NtValue::IntArray(vec![0, 10])),
+            (100, NtValue::IntArray(vec![10, 30])),
+        ];
+        let samples = join(JoinPolicy::Interpolate { tolerance }, &[25], &other).await;
+        assert_eq!(samples, vec![(25, 25, NtValue::IntArray(vec![3, 15]))]);
+    }
+
+    #[tokio::test]
+    async fn interpolate_falls_back_to_nearest() {
+        let other = vec![
+            (0, NtValue::String("a".to_owned())),
+            (100, NtValue::String("b".to_owned())),
+        ];
+        let tolerance = Duration::from_micros(500);
+        let samples = join(JoinPolicy::Interpolate { tolerance }, &[70], &other).await;
+        assert_eq!(samples, vec![(70, 100, NtValue::String("b".to_owned()))]);
+
+        // Arrays of different lengths can't be interpolated either
+        let other = vec![
+            (0, NtValue::DoubleArray(vec![1.0])),
+            (100, NtValue::DoubleArray(vec![1.0, 2.0])),
+        ];
+        let samples = join(JoinPolicy::Interpolate { tolerance }, &[30], &other).await;
+        assert_eq!(samples, vec![(30, 0, NtValue::DoubleArray(vec![1.0]))]);
+    }
+
+    #[test]
+    fn interpolate_across_wrap_around() {
+        let before = message(u32::MAX - 99, NtValue::Double(0.0));
+        let after = message(100, NtValue::Double(20.0));
+        let interpolated = interpolate(&before, &after, 0).unwrap();
+        assert_eq!(interpolated.timestamp, 0);
+        assert_eq!(interpolated.data, NtValue::Double(10.0));
+    }
+
+    #[tokio::test]
+    async fn next_is_cancel_safe() {
+        let (reference_sender, reference) = subscription();
+        let (other_sender, other) = subscription();
+        let mut join = Join::new(reference, vec![other], JoinPolicy::Exact)
+            .with_max_wait(Duration::from_secs(10));
+
+        reference_sender
+            .send(message(100, NtValue::Double(1.0)))
+            .await
+            .unwrap();
+        // Cancelled while waiting for the other value
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), join.next()).await;
+        assert!(cancelled.is_err());
+
+        other_sender
+            .send(message(100, NtValue::Double(2.0)))
+            .await
+            .unwrap();
+        let sample = join.next().await.unwrap();
+        assert_eq!(sample.timestamp, 100);
+        assert_eq!(sample.reference.data, NtValue::Double(1.0));
+        assert_eq!(sample.others[0].data, NtValue::Double(2.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_wait_forever() {
+        let (reference_sender, reference) = subscription();
+        let (other_sender, other) = subscription();
+        let mut join =
+            Join::new(reference, vec![other], JoinPolicy::Exact).with_max_wait(Duration::MAX);
+
+        reference_sender
+            .send(message(100, NtValue::Double(1.0)))
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+            other_sender
+                .send(message(100, NtValue::Double(2.0)))
+                .await
+                .unwrap();
+        });
+
+        let sample = join.next().await.unwrap();
+        assert_eq!(sample.others[0].data, NtValue::Double(2.0));
+    }
+}
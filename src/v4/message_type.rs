@@ -1,7 +1,10 @@
 use serde::{de::Visitor, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize)]
-#[serde(rename_all = "lowercase")]
+/// Type of a topic.
+///
+/// Types which aren't built into the spec (e.g. `struct:Pose2d` or `proto:Pose2d`) keep
+/// their full type string and are sent over the wire as raw bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Boolean,
     Double,
@@ -13,16 +16,19 @@ pub enum Type {
     Rpc,
     MsgPack,
     ProtoBuf,
-    #[serde(rename = "boolean[]")]
     BooleanArray,
-    #[serde(rename = "double[]")]
     DoubleArray,
-    #[serde(rename = "int[]")]
     IntArray,
-    #[serde(rename = "float[]")]
     FloatArray,
-    #[serde(rename = "string[]")]
     StringArray,
+    /// A WPILib struct, e.g. `struct:Pose2d`
+    Struct(String),
+    /// An array of WPILib structs, e.g. `struct:Pose2d[]`
+    StructArray(String),
+    /// A protobuf message, e.g. `proto:Pose2d`
+    Proto(String),
+    /// Any other type string
+    Other(String),
 }
 
 impl Type {
@@ -44,11 +50,12 @@ impl Type {
             Self::IntArray => 18,
             Self::FloatArray => 19,
             Self::StringArray => 20,
+            Self::Struct(_) | Self::StructArray(_) | Self::Proto(_) | Self::Other(_) => 5,
         }
     }
 
     #[inline]
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             Self::Boolean => "boolean",
             Self::Double => "double",
@@ -65,6 +72,19 @@ impl Type {
             Self::IntArray => "int[]",
             Self::FloatArray => "float[]",
             Self::StringArray => "string[]",
+            Self::Struct(str) | Self::StructArray(str) | Self::Proto(str) | Self::Other(str) => str,
+        }
+    }
+
+    /// Name of the struct or protobuf message for `struct:` and `proto:` types
+    pub fn type_name(&self) -> Option<&str> {
+        match self {
+            Self::Struct(str) => str.strip_prefix("struct:"),
+            Self::StructArray(str) => str
+                .strip_prefix("struct:")
+                .and_then(|str| str.strip_suffix("[]")),
+            Self::Proto(str) => str.strip_prefix("proto:"),
+            _ => None,
         }
     }
 
@@ -86,9 +106,11 @@ impl Type {
         }
     }
 
+    /// Returns `None` only for an empty type string, unknown types are kept as [`Type::Other`]
     #[inline]
     pub fn from_str(str: impl AsRef<str>) -> Option<Self> {
         match str.as_ref() {
+            "" => None,
             "boolean" => Some(Self::Boolean),
             "double" => Some(Self::Double),
             "int" => Some(Self::Int),
@@ -104,11 +126,25 @@ impl Type {
             "int[]" => Some(Self::IntArray),
             "float[]" => Some(Self::FloatArray),
             "string[]" => Some(Self::StringArray),
-            _ => None,
+            str if str.starts_with("struct:") && str.ends_with("[]") => {
+                Some(Self::StructArray(str.to_owned()))
+            }
+            str if str.starts_with("struct:") => Some(Self::Struct(str.to_owned())),
+            str if str.starts_with("proto:") => Some(Self::Proto(str.to_owned())),
+            str => Some(Self::Other(str.to_owned())),
         }
     }
 }
 
+impl Serialize for Type {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 struct MessageTypeVisitor;
 
 impl<'de> Visitor<'de> for MessageTypeVisitor {
@@ -134,3 +170,55 @@ impl<'de> serde::de::Deserialize<'de> for Type {
         deserializer.deserialize_str(MessageTypeVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v4::{Announce, NTMessage};
+
+    #[test]
+    fn custom_types_are_raw() {
+        let cases = [
+            ("struct:Pose2d", Type::Struct("struct:Pose2d".to_owned())),
+            (
+                "struct:Pose2d[]",
+                Type::StructArray("struct:Pose2d[]".to_owned()),
+            ),
+            ("proto:Pose2d", Type::Proto("proto:Pose2d".to_owned())),
+            ("photonvision", Type::Other("photonvision".to_owned())),
+        ];
+        for (str, expected) in cases {
+            let r#type = Type::from_str(str).unwrap();
+            assert_eq!(r#type, expected);
+            assert_eq!(r#type.as_u8(), 5);
+            assert_eq!(r#type.as_str(), str);
+        }
+
+        assert_eq!(Type::from_str(""), None);
+        assert_eq!(
+            Type::from_str("struct:Pose2d[]").unwrap().type_name(),
+            Some("Pose2d")
+        );
+    }
+
+    #[test]
+    fn announce_with_custom_type() {
+        let frame = r#"[
+            {"method": "announce", "params": {"name": "/pose", "id": 1, "type": "struct:Pose2d", "properties": {}}},
+            {"method": "announce", "params": {"name": "/speed", "id": 2, "type": "double", "properties": {}}}
+        ]"#;
+        let messages: Vec<NTMessage> = serde_json::from_str(frame).unwrap();
+        let types: Vec<_> = messages
+            .into_iter()
+            .map(|message| match message {
+                NTMessage::Announce(Announce { r#type, .. }) => r#type,
+                message => panic!("expected an announce, got {message:?}"),
+            })
+            .collect();
+
+        assert_eq!(
+            types,
+            [Type::Struct("struct:Pose2d".to_owned()), Type::Double]
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Meta topics the server publishes about its clients, publishers and subscribers.
+//!
+//! All of them have the `msgpack` type and are received as [`NtValue::Raw`].
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{NtValue, SubscriptionOptions};
+
+/// Prefix every meta topic starts with
+pub const META_PREFIX: &str = "$";
+
+/// Which meta topic a topic name refers to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MetaTopic {
+    /// `$clients`
+    Clients,
+    /// `$serverpub`
+    ServerPublishers,
+    /// `$serversub`
+    ServerSubscribers,
+    /// `$clientpub$<client>`
+    ClientPublishers(String),
+    /// `$clientsub$<client>`
+    ClientSubscribers(String),
+    /// `$pub$<topic>`
+    TopicPublishers(String),
+    /// `$sub$<topic>`
+    TopicSubscribers(String),
+}
+
+impl MetaTopic {
+    /// Returns `None` if the topic isn't a meta topic
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "$clients" => Self::Clients,
+            "$serverpub" => Self::ServerPublishers,
+            "$serversub" => Self::ServerSubscribers,
+            _ => {
+                if let Some(client) = name.strip_prefix("$clientpub$") {
+                    Self::ClientPublishers(client.to_owned())
+                } else if let Some(client) = name.strip_prefix("$clientsub$") {
+                    Self::ClientSubscribers(client.to_owned())
+                } else if let Some(topic) = name.strip_prefix("$pub$") {
+                    Self::TopicPublishers(topic.to_owned())
+                } else if let Some(topic) = name.strip_prefix("$sub$") {
+                    Self::TopicSubscribers(topic.to_owned())
+                } else {
+                    return None;
+                }
+            }
+        })
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Clients => "$clients".to_owned(),
+            Self::ServerPublishers => "$serverpub".to_owned(),
+            Self::ServerSubscribers => "$serversub".to_owned(),
+            Self::ClientPublishers(client) => format!("$clientpub${client}"),
+            Self::ClientSubscribers(client) => format!("$clientsub${client}"),
+            Self::TopicPublishers(topic) => format!("$pub${topic}"),
+            Self::TopicSubscribers(topic) => format!("$sub${topic}"),
+        }
+    }
+}
+
+/// Entry of `$clients`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientInfo {
+    /// Name of the client
+    pub id: String,
+    /// Connection info, usually the client's address
+    pub conn: String,
+}
+
+/// Entry of `$serverpub` & `$clientpub$<client>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublisherInfo {
+    /// Publisher uid
+    pub uid: i64,
+    pub topic: String,
+}
+
+/// Entry of `$serversub` & `$clientsub$<client>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscriberInfo {
+    /// Subscriber uid
+    pub uid: i64,
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub options: SubscriptionOptions,
+}
+
+/// Entry of `$pub$<topic>`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopicPublisher {
+    /// Name of the publishing client, empty for the server
+    pub client: String,
+    pub pubuid: i64,
+}
+
+/// Entry of `$sub$<topic>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopicSubscriber {
+    /// Name of the subscribing client, empty for the server
+    pub client: String,
+    pub subuid: i64,
+    #[serde(default)]
+    pub options: SubscriptionOptions,
+}
+
+/// Decoded value of a meta topic
+#[derive(Debug, Clone)]
+pub enum MetaValue {
+    Clients(Vec<ClientInfo>),
+    Publishers(Vec<PublisherInfo>),
+    Subscribers(Vec<SubscriberInfo>),
+    TopicPublishers(Vec<TopicPublisher>),
+    TopicSubscribers(Vec<TopicSubscriber>),
+}
+
+impl MetaValue {
+    /// Decodes the msgpack payload of a meta topic
+    pub fn decode(topic: &MetaTopic, payload: &[u8]) -> Result<Self, crate::Error> {
+        Ok(match topic {
+            MetaTopic::Clients => Self::Clients(rmp_serde::from_slice(payload)?),
+            MetaTopic::ServerPublishers | MetaTopic::ClientPublishers(_) => {
+                Self::Publishers(rmp_serde::from_slice(payload)?)
+            }
+            MetaTopic::ServerSubscribers | MetaTopic::ClientSubscribers(_) => {
+                Self::Subscribers(rmp_serde::from_slice(payload)?)
+            }
+            MetaTopic::TopicPublishers(_) => Self::TopicPublishers(rmp_serde::from_slice(payload)?),
+            MetaTopic::TopicSubscribers(_) => {
+                Self::TopicSubscribers(rmp_serde::from_slice(payload)?)
+            }
+        })
+    }
+
+    /// Decodes the value of a meta topic, which should be [`NtValue::Raw`]
+    pub fn from_value(topic: &MetaTopic, value: &NtValue) -> Result<Self, crate::Error> {
+        match value.as_bytes() {
+            Some(payload) => Self::decode(topic, payload),
+            None => Err(crate::Error::TypeMismatch {
+                expected: super::Type::MsgPack,
+                found: value.kind(),
+            }),
+        }
+    }
+}
+
+/// Everything the server has told us about its clients, publishers and subscribers
+#[derive(Debug, Clone, Default)]
+pub struct ServerModel {
+    pub clients: Vec<ClientInfo>,
+    /// Publishers of the server itself
+    pub server_publishers: Vec<PublisherInfo>,
+    /// Subscribers of the server itself
+    pub server_subscribers: Vec<SubscriberInfo>,
+    /// Keys are client names
+    pub client_publishers: HashMap<String, Vec<PublisherInfo>>,
+    /// Keys are client names
+    pub client_subscribers: HashMap<String, Vec<SubscriberInfo>>,
+    /// Keys are topic names
+    pub topic_publishers: HashMap<String, Vec<TopicPublisher>>,
+    /// Keys are topic names
+    pub topic_subscribers: HashMap<String, Vec<TopicSubscriber>>,
+}
+
+impl ServerModel {
+    /// Updates the model with a new value of a meta topic
+    pub fn apply(&mut self, topic: MetaTopic, value: MetaValue) {
+        /// Empty lists are removed so clients & topics which are gone don't stay around
+        fn set<T>(map: &mut HashMap<String, Vec<T>>, key: String, values: Vec<T>) {
+            if values.is_empty() {
+                map.remove(&key);
+            } else {
+                map.insert(key, values);
+            }
+        }
+
+        match (topic, value) {
+            (MetaTopic::Clients, MetaValue::Clients(clients)) => self.clients = clients,
+            (MetaTopic::ServerPublishers, MetaValue::Publishers(publishers)) => {
+                self.server_publishers = publishers
+            }
+            (MetaTopic::ServerSubscribers, MetaValue::Subscribers(subscribers)) => {
+                self.server_subscribers = subscribers
+            }
+            (MetaTopic::ClientPublishers(client), MetaValue::Publishers(publishers)) => {
+                set(&mut self.client_publishers, client, publishers)
+            }
+            (MetaTopic::ClientSubscribers(client), MetaValue::Subscribers(subscribers)) => {
+                set(&mut self.client_subscribers, client, subscribers)
+            }
+            (MetaTopic::TopicPublishers(topic), MetaValue::TopicPublishers(publishers)) => {
+                set(&mut self.topic_publishers, topic, publishers)
+            }
+            (MetaTopic::TopicSubscribers(topic), MetaValue::TopicSubscribers(subscribers)) => {
+                set(&mut self.topic_subscribers, topic, subscribers)
+            }
+            // Values are always decoded for their topic
+            _ => {}
+        }
+    }
+}
+
+/// Subscription to every meta topic which keeps a [`ServerModel`] up to date.
+///
+/// Created with [`Client::subscribe_meta`](super::Client::subscribe_meta).
+#[cfg(feature = "client-v4")]
+#[derive(Debug)]
+pub struct MetaSubscription {
+    pub(crate) subscription: super::Subscription,
+    pub(crate) model: ServerModel,
+}
+
+#[cfg(feature = "client-v4")]
+impl MetaSubscription {
+    pub fn model(&self) -> &ServerModel {
+        &self.model
+    }
+
+    /// Waits for the next update from the server and applies it to the model.
+    /// Values which fail to decode are skipped.
+    ///
+    /// Returns `None` if the client was dropped.
+    pub async fn next(&mut self) -> Option<&ServerModel> {
+        loop {
+            let message = self.subscription.next().await?;
+            let topic = match MetaTopic::from_name(&message.topic_name) {
+                Some(topic) => topic,
+                None => continue,
+            };
+
+            match MetaValue::from_value(&topic, &message.data) {
+                Ok(value) => {
+                    self.model.apply(topic, value);
+                    return Some(&self.model);
+                }
+                Err(_err) => {
+                    cfg_tracing! {
+                        tracing::warn!("Failed to decode meta topic {}: {_err}", message.topic_name);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn into_subscription(self) -> super::Subscription {
+        self.subscription
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decode(topic: &str, payload: serde_json::Value) -> (MetaTopic, MetaValue) {
+        let topic = MetaTopic::from_name(topic).unwrap();
+        let payload = rmp_serde::to_vec_named(&payload).unwrap();
+        let value = MetaValue::decode(&topic, &payload).unwrap();
+        (topic, value)
+    }
+
+    #[test]
+    fn topic_names() {
+        for name in [
+            "$clients",
+            "$serverpub",
+            "$sub$/a/b",
+            "$clientpub$dashboard@1",
+        ] {
+            assert_eq!(MetaTopic::from_name(name).unwrap().name(), name);
+        }
+        assert_eq!(
+            MetaTopic::from_name("$pub$$sub$x"),
+            Some(MetaTopic::TopicPublishers("$sub$x".to_owned()))
+        );
+        assert_eq!(MetaTopic::from_name("/a"), None);
+        assert_eq!(MetaTopic::from_name("$other"), None);
+    }
+
+    #[test]
+    fn decodes_clients() {
+        let (topic, value) = decode(
+            "$clients",
+            json!([{"id": "dashboard@1", "conn": "10.0.0.2:5810"}]),
+        );
+        let mut model = ServerModel::default();
+        model.apply(topic, value);
+        assert_eq!(
+            model.clients,
+            [ClientInfo {
+                id: "dashboard@1".to_owned(),
+                conn: "10.0.0.2:5810".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn decodes_server_publishers() {
+        let (topic, value) = decode(
+            "$serverpub",
+            json!([{"uid": 3, "topic": "/a"}, {"uid": -1, "topic": "$clients"}]),
+        );
+        let mut model = ServerModel::default();
+        model.apply(topic, value);
+        assert_eq!(
+            model.server_publishers,
+            [
+                PublisherInfo {
+                    uid: 3,
+                    topic: "/a".to_owned(),
+                },
+                PublisherInfo {
+                    uid: -1,
+                    topic: "$clients".to_owned(),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_topic_subscribers() {
+        let (topic, value) = decode(
+            "$sub$/a",
+            json!([
+                {"client": "dashboard@1", "subuid": 5, "options": {"prefix": true, "periodic": 0.1, "topicsonly": true}},
+                {"client": "", "subuid": 1}
+            ]),
+        );
+        let mut model = ServerModel::default();
+        model.apply(topic, value);
+
+        let subscribers = &model.topic_subscribers["/a"];
+        assert_eq!(subscribers.len(), 2);
+        assert_eq!(subscribers[0].client, "dashboard@1");
+        assert_eq!(subscribers[0].subuid, 5);
+        assert_eq!(subscribers[0].options.prefix, Some(true));
+        assert_eq!(subscribers[0].options.periodic, Some(0.1));
+        assert_eq!(subscribers[0].options.topics_only, Some(true));
+        assert_eq!(subscribers[1].client, "");
+        assert_eq!(subscribers[1].options.prefix, None);
+    }
+
+    #[test]
+    fn empty_list_removes_entry() {
+        let mut model = ServerModel::default();
+        let (topic, value) = decode("$pub$/a", json!([{"client": "", "pubuid": 2}]));
+        model.apply(topic, value);
+        assert_eq!(model.topic_publishers["/a"].len(), 1);
+
+        let (topic, value) = decode("$pub$/a", json!([]));
+        model.apply(topic, value);
+        assert!(!model.topic_publishers.contains_key("/a"));
+    }
+
+    #[test]
+    fn wrong_payload() {
+        let topic = MetaTopic::Clients;
+        assert!(MetaValue::decode(&topic, &[0xc1]).is_err());
+        assert!(matches!(
+            MetaValue::from_value(&topic, &NtValue::Int(1)),
+            Err(crate::Error::TypeMismatch { found: "int", .. })
+        ));
+    }
+}
@@ -1,18 +1,63 @@
 #[cfg(feature = "client-v4")]
+pub mod adapters;
+#[cfg(feature = "client-v4")]
+pub mod blocking;
+#[cfg(feature = "client-v4")]
 pub mod client;
 #[cfg(feature = "client-v4")]
 pub mod client_config;
+#[cfg(feature = "client-v4")]
+pub mod clock;
+#[cfg(feature = "client-v4")]
+pub mod connection_state;
+#[cfg(feature = "client-v4")]
+pub mod diagnostics;
+pub mod encode;
+#[cfg(feature = "client-v4")]
+pub mod entry;
+#[cfg(feature = "client-v4")]
+mod frame_batcher;
+#[cfg(feature = "client-v4")]
+pub mod join;
 pub mod message_type;
 pub mod messages;
+pub mod meta;
+#[cfg(feature = "client-v4")]
+pub mod publish_batch;
 pub mod subscription;
+#[cfg(feature = "client-v4")]
+pub mod time_sync;
 pub mod topic;
+pub mod value;
+#[cfg(feature = "client-v4")]
+pub mod watchdog;
 
+pub use encode::EncodeValue;
 pub use message_type::*;
 pub use messages::*;
 pub use subscription::*;
 pub use topic::*;
+pub use value::NtValue;
 
+#[cfg(feature = "client-v4")]
+pub use adapters::MessageStreamExt;
 #[cfg(feature = "client-v4")]
 pub use client::Client;
 #[cfg(feature = "client-v4")]
 pub use client_config::Config;
+#[cfg(feature = "client-v4")]
+pub use clock::{Clock, ManualClock, SystemClock};
+#[cfg(feature = "client-v4")]
+pub use connection_state::ConnectionState;
+#[cfg(feature = "client-v4")]
+pub use diagnostics::Diagnostic;
+#[cfg(feature = "client-v4")]
+pub use entry::Entry;
+#[cfg(feature = "client-v4")]
+pub use join::{Join, JoinPolicy, JoinedSample};
+#[cfg(feature = "client-v4")]
+pub use publish_batch::PublishBatch;
+#[cfg(feature = "client-v4")]
+pub use time_sync::TimeSyncStats;
+#[cfg(feature = "client-v4")]
+pub use watchdog::{Watchdog, WatchdogEvent, WatchdogHandle};
@@ -0,0 +1,80 @@
+use super::{encode, Client, EncodeValue, PublishedTopic};
+
+/// Values for several topics which are published together, sent in a single frame with one timestamp.
+///
+/// Created with [`Client::publish_batch`]. Subscribers still receive each value as a separate message.
+#[derive(Debug)]
+pub struct PublishBatch<'a> {
+    client: &'a Client,
+    timestamp: u32,
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl<'a> PublishBatch<'a> {
+    pub(crate) fn new(client: &'a Client, timestamp: u32, buf: Vec<u8>) -> Self {
+        Self {
+            client,
+            timestamp,
+            buf,
+            len: 0,
+        }
+    }
+
+    /// Timestamp all values are published with
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    /// Number of values added
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a value for a topic, see [`Client::publish_value`] for which values are accepted.
+    /// The batch is unchanged if the value doesn't match the topic's type or the topic has a type conflict.
+    pub fn add(
+        &mut self,
+        topic: &PublishedTopic,
+        value: impl EncodeValue,
+    ) -> Result<&mut Self, crate::Error> {
+        self.client.check_type_conflict(topic)?;
+        let start = self.buf.len();
+        if let Err(err) = encode::write_value_message(
+            &mut self.buf,
+            topic.pubuid.into(),
+            self.timestamp,
+            &topic.r#type,
+            value,
+        ) {
+            self.buf.truncate(start);
+            return Err(err);
+        }
+
+        self.len += 1;
+        Ok(self)
+    }
+
+    /// Sends all values, does nothing if the batch is empty
+    pub async fn send(mut self) -> Result<(), crate::Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let buf = std::mem::take(&mut self.buf);
+        self.client.send_encoded(buf).await
+    }
+}
+
+impl Drop for PublishBatch<'_> {
+    fn drop(&mut self) {
+        // Not sent, the buffer can be reused right away
+        if self.buf.capacity() > 0 {
+            self.client.recycle_buffer(std::mem::take(&mut self.buf));
+        }
+    }
+}
@@ -1,23 +1,22 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     sync::{Arc, Weak},
+    time::Duration,
 };
 
 use futures_util::Stream;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 
-use super::{
-    messages::{NTMessage, Unsubscribe},
-    Topic, Type,
-};
+use super::{NtValue, Type};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageData {
     pub topic_name: String,
     pub timestamp: u32,
+    /// The announced type of the topic, including the full type string for custom types
     pub r#type: Type,
-    pub data: rmpv::Value,
+    pub data: NtValue,
 }
 
 #[derive(Debug, Clone)]
@@ -30,13 +29,97 @@ pub struct SubscriptionData {
 #[derive(Debug)]
 pub struct InternalSub {
     pub(crate) data: Weak<SubscriptionData>,
-    pub(crate) sender: mpsc::Sender<MessageData>,
+    pub(crate) sender: SubSender,
+}
+
+/// Values a subscription's queue holds before new ones are dropped
+pub(crate) const SUBSCRIPTION_QUEUE_SIZE: usize = 256;
+
+/// Reads values of a subscription without waiting, similar to ntcore's `readQueue` & `get`.
+///
+/// Created with [`Subscription::into_sync`].
+#[derive(Debug)]
+pub struct SyncSubscription {
+    subscription: Subscription,
+    /// Values taken from the queue by [`Self::get_latest`] which [`Self::read_queue`] hasn't returned yet, oldest first
+    unread: VecDeque<Arc<MessageData>>,
+    latest: Option<Arc<MessageData>>,
+}
+
+impl SyncSubscription {
+    fn receive(&mut self) {
+        while let Ok(message) = self.subscription.receiver.try_recv() {
+            // Like the queue itself, only hold so many values for a reader which never reads them
+            if self.unread.len() == SUBSCRIPTION_QUEUE_SIZE {
+                self.unread.pop_front();
+            }
+            self.latest = Some(Arc::clone(&message));
+            self.unread.push_back(message);
+        }
+    }
+
+    /// All values received since the last call, oldest first.
+    /// Calling [`Self::get_latest`] doesn't remove values from it.
+    pub fn read_queue(&mut self) -> Vec<Arc<MessageData>> {
+        self.receive();
+        self.unread.drain(..).collect()
+    }
+
+    /// Newest value received, even if it was already returned by [`Self::read_queue`]
+    pub fn get_latest(&mut self) -> Option<Arc<MessageData>> {
+        self.receive();
+        self.latest.clone()
+    }
+
+    pub fn into_inner(self) -> Subscription {
+        self.subscription
+    }
+}
+
+/// Newest value of each topic of a [`LatestSubscription`], keys are topic names
+pub type LatestValues = HashMap<String, Arc<MessageData>>;
+
+/// Where the values of a subscription are sent
+#[derive(Debug)]
+pub(crate) enum SubSender {
+    Queue(mpsc::Sender<Arc<MessageData>>),
+    /// Only the newest value of each topic is kept
+    Latest(watch::Sender<LatestValues>),
+}
+
+pub(crate) enum SendError {
+    Full,
+    Closed,
+}
+
+impl SubSender {
+    pub(crate) fn send(&self, message: Arc<MessageData>) -> Result<(), SendError> {
+        match self {
+            Self::Queue(sender) => sender.try_send(message).map_err(|err| match err {
+                mpsc::error::TrySendError::Full(_) => SendError::Full,
+                mpsc::error::TrySendError::Closed(_) => SendError::Closed,
+            }),
+            Self::Latest(sender) => {
+                if sender.is_closed() {
+                    return Err(SendError::Closed);
+                }
+
+                sender.send_modify(|values| match values.get_mut(&message.topic_name) {
+                    Some(latest) => *latest = message,
+                    None => {
+                        values.insert(message.topic_name.clone(), message);
+                    }
+                });
+                Ok(())
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Subscription {
     pub(crate) data: Arc<SubscriptionData>,
-    pub(crate) receiver: mpsc::Receiver<MessageData>,
+    pub(crate) receiver: mpsc::Receiver<Arc<MessageData>>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -46,7 +129,7 @@ pub struct SubscriptionOptions {
     pub periodic: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub all: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "topicsonly", skip_serializing_if = "Option::is_none")]
     pub topics_only: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub prefix: Option<bool>,
@@ -59,7 +142,7 @@ impl InternalSub {
         self.data.strong_count() != 0
     }
 
-    pub(crate) fn matches_topic(&self, topic: &Topic) -> bool {
+    pub(crate) fn matches_topic(&self, topic_name: &str) -> bool {
         if let Some(data) = self.data.upgrade() {
             let prefix = data
                 .options
@@ -70,11 +153,9 @@ impl InternalSub {
             if prefix {
                 data.topics
                     .iter()
-                    .any(|topic_pat| topic.name.starts_with(topic_pat))
+                    .any(|topic_pat| topic_name.starts_with(topic_pat))
             } else {
-                data.topics
-                    .iter()
-                    .any(|topic_name| *topic_name == topic.name)
+                data.topics.iter().any(|name| name == topic_name)
             }
         } else {
             false
@@ -83,26 +164,30 @@ impl InternalSub {
 }
 
 impl Subscription {
-    pub(crate) fn as_unsubscribe(&self) -> NTMessage {
-        NTMessage::Unsubscribe(Unsubscribe {
-            subuid: self.data.subuid,
-        })
+    /// Messages are shared between every subscription the topic matches
+    pub async fn next(&mut self) -> Option<Arc<MessageData>> {
+        self.receiver.recv().await
     }
 
-    pub async fn next(&mut self) -> Option<MessageData> {
-        self.receiver.recv().await
+    /// Handle for reading values without `.await`, e.g. from a control loop thread
+    pub fn into_sync(self) -> SyncSubscription {
+        SyncSubscription {
+            subscription: self,
+            unread: VecDeque::new(),
+            latest: None,
+        }
     }
 
     pub fn poll_next(
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<MessageData>> {
+    ) -> std::task::Poll<Option<Arc<MessageData>>> {
         self.receiver.poll_recv(cx)
     }
 }
 
 impl Stream for Subscription {
-    type Item = MessageData;
+    type Item = Arc<MessageData>;
 
     fn poll_next(
         self: std::pin::Pin<&mut Self>,
@@ -111,3 +196,178 @@ impl Stream for Subscription {
         self.poll_next(cx)
     }
 }
+
+/// Subscription which only keeps the newest value of each topic, so slow readers never fall behind.
+///
+/// Created with [`Client::subscribe_latest`](super::Client::subscribe_latest).
+#[derive(Debug)]
+pub struct LatestSubscription {
+    pub(crate) data: Arc<SubscriptionData>,
+    pub(crate) receiver: watch::Receiver<LatestValues>,
+}
+
+impl LatestSubscription {
+    /// Newest value of a topic, `None` if none was received yet
+    pub fn get(&self, topic_name: &str) -> Option<Arc<MessageData>> {
+        self.receiver.borrow().get(topic_name).cloned()
+    }
+
+    /// Newest value of every topic received so far
+    pub fn snapshot(&self) -> LatestValues {
+        self.receiver.borrow().clone()
+    }
+
+    /// Waits until a value was received since the last call to this, [`Self::snapshot_and_update`] or [`Resampled::next`].
+    /// Returns `false` if the client was dropped.
+    pub async fn changed(&mut self) -> bool {
+        self.receiver.changed().await.is_ok()
+    }
+
+    /// Newest value of every topic, marking them as seen for [`Self::changed`]
+    pub fn snapshot_and_update(&mut self) -> LatestValues {
+        self.receiver.borrow_and_update().clone()
+    }
+
+    /// Yields the newest values at most once per `period`, skipping periods without new values.
+    /// Useful when the server ignores [`SubscriptionOptions::periodic`] or the reader is slower than the values arrive.
+    ///
+    /// Periods shorter than a millisecond are rounded up to one.
+    pub fn resample(self, period: Duration) -> Resampled {
+        let mut interval = tokio::time::interval(period.max(Duration::from_millis(1)));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        Resampled {
+            subscription: self,
+            interval,
+        }
+    }
+}
+
+/// A [`LatestSubscription`] sampled at a fixed rate, created with [`LatestSubscription::resample`]
+#[derive(Debug)]
+pub struct Resampled {
+    subscription: LatestSubscription,
+    interval: tokio::time::Interval,
+}
+
+impl Resampled {
+    /// Waits for the next period with new values.
+    /// Returns `None` if the client was dropped.
+    pub async fn next(&mut self) -> Option<LatestValues> {
+        self.interval.tick().await;
+        match self.subscription.receiver.has_changed() {
+            Ok(true) => {}
+            Ok(false) => {
+                // Don't wake up every period while nothing is being published
+                if !self.subscription.changed().await {
+                    return None;
+                }
+                self.interval.reset();
+            }
+            Err(_) => return None,
+        }
+
+        Some(self.subscription.snapshot_and_update())
+    }
+
+    pub fn into_inner(self) -> LatestSubscription {
+        self.subscription
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> Arc<SubscriptionData> {
+        Arc::new(SubscriptionData {
+            subuid: 0,
+            topics: HashSet::new(),
+            options: None,
+        })
+    }
+
+    fn message(topic_name: &str, value: i64) -> Arc<MessageData> {
+        MessageData::received(topic_name, 0, Type::Int, NtValue::Int(value))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resample_with_zero_period() {
+        let (sender, receiver) = watch::channel(LatestValues::new());
+        let sender = SubSender::Latest(sender);
+        let subscription = LatestSubscription {
+            data: data(),
+            receiver,
+        };
+        let mut resampled = subscription.resample(Duration::ZERO);
+
+        sender.send(message("/a", 1)).ok();
+        sender.send(message("/a", 2)).ok();
+        let values = resampled.next().await.unwrap();
+        assert_eq!(values["/a"].data, NtValue::Int(2));
+
+        drop(sender);
+        assert!(resampled.next().await.is_none());
+    }
+
+    fn sync_subscription() -> (mpsc::Sender<Arc<MessageData>>, SyncSubscription) {
+        let (sender, receiver) = mpsc::channel(SUBSCRIPTION_QUEUE_SIZE);
+        (sender, Subscription::from_receiver(receiver).into_sync())
+    }
+
+    fn values(messages: &[Arc<MessageData>]) -> Vec<i64> {
+        messages
+            .iter()
+            .filter_map(|message| message.data.as_i64())
+            .collect()
+    }
+
+    #[test]
+    fn get_latest_keeps_queue() {
+        let (sender, mut subscription) = sync_subscription();
+        assert!(subscription.get_latest().is_none());
+
+        sender.try_send(message("/a", 1)).unwrap();
+        sender.try_send(message("/a", 2)).unwrap();
+        assert_eq!(subscription.get_latest().unwrap().data, NtValue::Int(2));
+        sender.try_send(message("/a", 3)).unwrap();
+        assert_eq!(values(&subscription.read_queue()), [1, 2, 3]);
+
+        // Values aren't returned twice, but the latest one stays
+        assert!(subscription.read_queue().is_empty());
+        assert_eq!(subscription.get_latest().unwrap().data, NtValue::Int(3));
+    }
+
+    #[test]
+    fn unread_values_are_bounded() {
+        let (sender, mut subscription) = sync_subscription();
+        for value in 0..SUBSCRIPTION_QUEUE_SIZE as i64 {
+            sender.try_send(message("/a", value)).unwrap();
+        }
+        subscription.get_latest();
+        sender.try_send(message("/a", -1)).unwrap();
+
+        let values = values(&subscription.read_queue());
+        assert_eq!(values.len(), SUBSCRIPTION_QUEUE_SIZE);
+        assert_eq!(values.first(), Some(&1));
+        assert_eq!(values.last(), Some(&-1));
+    }
+
+    #[test]
+    fn options_use_spec_keys() {
+        let options = SubscriptionOptions {
+            topics_only: Some(true),
+            prefix: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&options).unwrap(),
+            serde_json::json!({"topicsonly": true, "prefix": true})
+        );
+
+        let options: SubscriptionOptions =
+            serde_json::from_str(r#"{"topicsonly": true, "periodic": 0.5}"#).unwrap();
+        assert_eq!(options.topics_only, Some(true));
+        assert_eq!(options.periodic, Some(0.5));
+        assert!(options.rest.is_none_or(|rest| rest.is_empty()));
+    }
+}
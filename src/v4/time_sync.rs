@@ -0,0 +1,210 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// How well the client's clock is synchronized with the server's.
+///
+/// Returned by [`Client::time_sync_stats`](super::Client::time_sync_stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSyncStats {
+    /// Microseconds added (wrapping) to client time to get server time
+    pub offset: u32,
+    /// Round trip time of the sample the offset is taken from
+    pub rtt: Duration,
+    /// Mean deviation of the round trip times of the recent samples
+    pub jitter: Duration,
+    /// Number of recent samples the estimate is based on
+    pub samples: usize,
+}
+
+/// Difference between two server timestamps in microseconds, handling them wrapping around
+pub(crate) fn time_diff(a: u32, b: u32) -> i64 {
+    a.wrapping_sub(b) as i32 as i64
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    /// microseconds
+    rtt: u32,
+    offset: u32,
+}
+
+/// Estimates the server time offset from the most recent time sync samples.
+///
+/// The offset of the sample with the lowest round trip time is used,
+/// since that sample was least affected by network delays.
+#[derive(Debug)]
+pub(crate) struct TimeSync {
+    max_samples: usize,
+    samples: VecDeque<Sample>,
+    offset: u32,
+}
+
+impl TimeSync {
+    pub(crate) fn new(max_samples: usize) -> Self {
+        Self {
+            max_samples: max_samples.max(1),
+            samples: VecDeque::new(),
+            offset: 0,
+        }
+    }
+
+    pub(crate) fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Forgets all samples, used when the client's start time is reset
+    pub(crate) fn reset(&mut self) {
+        self.samples.clear();
+        self.offset = 0;
+    }
+
+    /// Keeps the estimate valid after client time moved back by `micros`
+    pub(crate) fn shift(&mut self, micros: u32) {
+        self.offset = self.offset.wrapping_add(micros);
+        for sample in &mut self.samples {
+            sample.offset = sample.offset.wrapping_add(micros);
+        }
+    }
+
+    /// Adds a sample, all times are in microseconds.
+    /// Returns `None` if the response was sent before the client's time was reset.
+    pub(crate) fn add_sample(
+        &mut self,
+        client_send_time: u32,
+        server_time: u32,
+        client_receive_time: u32,
+    ) -> Option<()> {
+        let rtt = client_receive_time.checked_sub(client_send_time)?;
+        // The server sent its time halfway through the round trip
+        let server_time_at_receive = server_time.wrapping_add(rtt / 2);
+        let offset = server_time_at_receive.wrapping_sub(client_receive_time);
+
+        if self.samples.len() == self.max_samples {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { rtt, offset });
+
+        if let Some(best) = self.best_sample() {
+            self.offset = best.offset;
+        }
+
+        Some(())
+    }
+
+    fn best_sample(&self) -> Option<&Sample> {
+        self.samples.iter().min_by_key(|sample| sample.rtt)
+    }
+
+    pub(crate) fn stats(&self) -> Option<TimeSyncStats> {
+        let best = self.best_sample()?;
+        let count = self.samples.len() as u64;
+        let mean_rtt = self
+            .samples
+            .iter()
+            .map(|sample| sample.rtt as u64)
+            .sum::<u64>()
+            / count;
+        let jitter = self
+            .samples
+            .iter()
+            .map(|sample| (sample.rtt as u64).abs_diff(mean_rtt))
+            .sum::<u64>()
+            / count;
+
+        Some(TimeSyncStats {
+            offset: self.offset,
+            rtt: Duration::from_micros(best.rtt.into()),
+            jitter: Duration::from_micros(jitter),
+            samples: self.samples.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_diff_wraps_around() {
+        assert_eq!(time_diff(5, u32::MAX - 4), 10);
+        assert_eq!(time_diff(u32::MAX - 4, 5), -10);
+        assert_eq!(time_diff(100, 40), 60);
+    }
+
+    #[test]
+    fn offset_is_from_halfway_through_the_round_trip() {
+        let mut time_sync = TimeSync::new(8);
+        assert_eq!(time_sync.add_sample(1000, 50_000, 1200), Some(()));
+        // Server time at receive is 50_100, client time is 1200
+        assert_eq!(time_sync.offset(), 48_900);
+    }
+
+    #[test]
+    fn uses_sample_with_lowest_rtt() {
+        let mut time_sync = TimeSync::new(8);
+        time_sync.add_sample(0, 1000, 100).unwrap();
+        time_sync.add_sample(200, 2000, 500).unwrap();
+        assert_eq!(time_sync.offset(), 950);
+
+        let stats = time_sync.stats().unwrap();
+        assert_eq!(stats.offset, 950);
+        assert_eq!(stats.rtt, Duration::from_micros(100));
+        // Mean rtt is 200us, both samples are 100us from it
+        assert_eq!(stats.jitter, Duration::from_micros(100));
+        assert_eq!(stats.samples, 2);
+    }
+
+    #[test]
+    fn forgets_old_samples() {
+        let mut time_sync = TimeSync::new(2);
+        time_sync.add_sample(0, 1000, 100).unwrap();
+        time_sync.add_sample(200, 2000, 500).unwrap();
+        time_sync.add_sample(600, 3000, 1000).unwrap();
+
+        // The best sample was dropped, the 300us one is best now
+        let stats = time_sync.stats().unwrap();
+        assert_eq!(stats.rtt, Duration::from_micros(300));
+        assert_eq!(stats.offset, 1650);
+        assert_eq!(stats.samples, 2);
+    }
+
+    #[test]
+    fn rejects_responses_from_before_a_reset() {
+        let mut time_sync = TimeSync::new(8);
+        assert_eq!(time_sync.add_sample(500, 1000, 100), None);
+        assert!(time_sync.stats().is_none());
+    }
+
+    #[test]
+    fn offset_wraps_around() {
+        let mut time_sync = TimeSync::new(8);
+        time_sync.add_sample(0, u32::MAX - 10, 100).unwrap();
+        assert_eq!(
+            time_sync.offset(),
+            (u32::MAX - 10).wrapping_add(50).wrapping_sub(100)
+        );
+    }
+
+    #[test]
+    fn shift_moves_every_sample() {
+        let mut time_sync = TimeSync::new(8);
+        time_sync.add_sample(0, 1000, 100).unwrap();
+        time_sync.add_sample(200, 2000, 500).unwrap();
+
+        time_sync.shift(10_000);
+        assert_eq!(time_sync.offset(), 10_950);
+
+        // Client time restarted 10ms later, a sample agreeing with the others keeps the offset
+        time_sync.add_sample(0, 12_000, 100).unwrap();
+        assert_eq!(time_sync.offset(), 10_950);
+        assert_eq!(time_sync.stats().unwrap().samples, 3);
+    }
+
+    #[test]
+    fn reset_forgets_samples() {
+        let mut time_sync = TimeSync::new(8);
+        time_sync.add_sample(0, 1000, 100).unwrap();
+        time_sync.reset();
+        assert_eq!(time_sync.offset(), 0);
+        assert!(time_sync.stats().is_none());
+    }
+}
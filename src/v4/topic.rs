@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::{Deserialize, Serialize};
 
@@ -22,6 +22,7 @@ pub struct Topic {
     pub name: String,
     pub id: i32,
     pub pubuid: Option<i32>,
+    /// Type exactly as announced by the server, e.g. `struct:Pose2d`
     pub r#type: Type,
     pub properties: Option<PublishProperties>,
 }
@@ -47,3 +48,134 @@ impl PublishedTopic {
         })
     }
 }
+
+/// Topics announced by the server, indexed by id & name
+#[derive(Debug, Default)]
+pub(crate) struct AnnouncedTopics {
+    by_id: HashMap<i32, Topic>,
+    // Sorted so topics under a prefix are found without a scan
+    ids_by_name: BTreeMap<String, i32>,
+}
+
+impl AnnouncedTopics {
+    pub(crate) fn get(&self, id: i32) -> Option<&Topic> {
+        self.by_id.get(&id)
+    }
+
+    pub(crate) fn get_by_name(&self, name: &str) -> Option<&Topic> {
+        self.ids_by_name.get(name).and_then(|id| self.by_id.get(id))
+    }
+
+    /// Topics whose name starts with `prefix`, sorted by name
+    pub(crate) fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a Topic> {
+        self.ids_by_name
+            .range::<str, _>((
+                std::ops::Bound::Included(prefix),
+                std::ops::Bound::Unbounded,
+            ))
+            .take_while(move |(name, _)| name.starts_with(prefix))
+            .filter_map(|(_, id)| self.by_id.get(id))
+    }
+
+    /// Adds or replaces the topic with the same id, returning the previous one
+    pub(crate) fn insert(&mut self, topic: Topic) -> Option<Topic> {
+        let previous = self.remove(topic.id);
+        // Another topic can't have the same name, but don't leave a dangling id if the server was wrong
+        if let Some(other_id) = self.ids_by_name.insert(topic.name.clone(), topic.id) {
+            self.by_id.remove(&other_id);
+        }
+        self.by_id.insert(topic.id, topic);
+        previous
+    }
+
+    pub(crate) fn remove(&mut self, id: i32) -> Option<Topic> {
+        let topic = self.by_id.remove(&id)?;
+        if self.ids_by_name.get(&topic.name) == Some(&id) {
+            self.ids_by_name.remove(&topic.name);
+        }
+        Some(topic)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.by_id.clear();
+        self.ids_by_name.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic(name: &str, id: i32) -> Topic {
+        Topic {
+            name: name.to_owned(),
+            id,
+            pubuid: None,
+            r#type: Type::Double,
+            properties: None,
+        }
+    }
+
+    fn names<'a>(topics: impl Iterator<Item = &'a Topic>) -> Vec<&'a str> {
+        topics.map(|topic| topic.name.as_str()).collect()
+    }
+
+    #[test]
+    fn insert_replaces_by_id() {
+        let mut topics = AnnouncedTopics::default();
+        assert!(topics.insert(topic("/a", 1)).is_none());
+
+        let mut renamed = topic("/b", 1);
+        renamed.r#type = Type::Int;
+        assert_eq!(
+            topics.insert(renamed).map(|old| old.name).as_deref(),
+            Some("/a")
+        );
+        assert!(topics.get_by_name("/a").is_none());
+        assert_eq!(
+            topics.get_by_name("/b").map(|topic| &topic.r#type),
+            Some(&Type::Int)
+        );
+        assert_eq!(topics.get(1).map(|topic| topic.name.as_str()), Some("/b"));
+    }
+
+    #[test]
+    fn name_collision_across_ids() {
+        let mut topics = AnnouncedTopics::default();
+        topics.insert(topic("/a", 1));
+        // The server reused the name without unannouncing the old id
+        assert!(topics.insert(topic("/a", 2)).is_none());
+        assert!(topics.get(1).is_none());
+        assert_eq!(topics.get_by_name("/a").map(|topic| topic.id), Some(2));
+
+        // Removing the stale id doesn't remove the name of the new one
+        assert!(topics.remove(1).is_none());
+        assert_eq!(topics.get_by_name("/a").map(|topic| topic.id), Some(2));
+        assert_eq!(topics.remove(2).map(|topic| topic.id), Some(2));
+        assert!(topics.get_by_name("/a").is_none());
+    }
+
+    #[test]
+    fn with_prefix_bounds() {
+        let mut topics = AnnouncedTopics::default();
+        for (id, name) in ["/a", "/a/b", "/a/c", "/a0", "/ab", "/b", "/"]
+            .iter()
+            .enumerate()
+        {
+            topics.insert(topic(name, id as i32));
+        }
+
+        assert_eq!(names(topics.with_prefix("/a/")), ["/a/b", "/a/c"]);
+        assert_eq!(
+            names(topics.with_prefix("/a")),
+            ["/a", "/a/b", "/a/c", "/a0", "/ab"]
+        );
+        assert_eq!(names(topics.with_prefix("/b")), ["/b"]);
+        assert_eq!(names(topics.with_prefix("/c")), Vec::<&str>::new());
+        assert_eq!(names(topics.with_prefix("")).len(), 7);
+        assert_eq!(names(topics.with_prefix("/")).len(), 7);
+
+        topics.clear();
+        assert_eq!(names(topics.with_prefix("")), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,344 @@
+use bytes::Bytes;
+use rmpv::ValueRef;
+use serde::{Deserialize, Serialize};
+
+use super::Type;
+
+/// A value of a network tables topic.
+///
+/// `json` topics are represented as [`NtValue::String`] and
+/// `raw`, `rpc`, `msgpack`, `protobuf`, `struct:` & `proto:` topics as [`NtValue::Raw`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NtValue {
+    Boolean(bool),
+    Double(f64),
+    Int(i64),
+    Float(f32),
+    String(String),
+    /// Shares the memory of the websocket frame it was received in
+    Raw(Bytes),
+    BooleanArray(Vec<bool>),
+    DoubleArray(Vec<f64>),
+    IntArray(Vec<i64>),
+    FloatArray(Vec<f32>),
+    StringArray(Vec<String>),
+}
+
+impl NtValue {
+    /// Name of the kind of value, used for error messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Boolean(_) => "boolean",
+            Self::Double(_) => "double",
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::String(_) => "string",
+            Self::Raw(_) => "raw",
+            Self::BooleanArray(_) => "boolean[]",
+            Self::DoubleArray(_) => "double[]",
+            Self::IntArray(_) => "int[]",
+            Self::FloatArray(_) => "float[]",
+            Self::StringArray(_) => "string[]",
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a `f64` if it is any numeric type
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Double(value) => Some(*value),
+            Self::Float(value) => Some(*value as f64),
+            Self::Int(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Raw(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+fn value_kind(value: &ValueRef) -> &'static str {
+    match value {
+        ValueRef::Nil => "nil",
+        ValueRef::Boolean(_) => "boolean",
+        ValueRef::Integer(_) => "integer",
+        ValueRef::F32(_) => "f32",
+        ValueRef::F64(_) => "f64",
+        ValueRef::String(_) => "string",
+        ValueRef::Binary(_) => "binary",
+        ValueRef::Array(_) => "array",
+        ValueRef::Map(_) => "map",
+        ValueRef::Ext(..) => "ext",
+    }
+}
+
+fn mismatch(expected: &Type, value: &ValueRef) -> crate::Error {
+    crate::Error::TypeMismatch {
+        expected: expected.clone(),
+        found: value_kind(value),
+    }
+}
+
+fn to_bool(r#type: &Type, value: &ValueRef) -> Result<bool, crate::Error> {
+    match value {
+        ValueRef::Boolean(value) => Ok(*value),
+        value => Err(mismatch(r#type, value)),
+    }
+}
+
+/// Any numeric msgpack value is accepted for doubles & floats
+fn to_f64(r#type: &Type, value: &ValueRef) -> Result<f64, crate::Error> {
+    match value {
+        ValueRef::F64(value) => Ok(*value),
+        ValueRef::F32(value) => Ok(*value as f64),
+        ValueRef::Integer(int) => int.as_f64().ok_or_else(|| mismatch(r#type, value)),
+        value => Err(mismatch(r#type, value)),
+    }
+}
+
+fn to_i64(r#type: &Type, value: &ValueRef) -> Result<i64, crate::Error> {
+    match value {
+        ValueRef::Integer(int) => int.as_i64().ok_or_else(|| mismatch(r#type, value)),
+        value => Err(mismatch(r#type, value)),
+    }
+}
+
+fn to_string(r#type: &Type, value: &ValueRef) -> Result<String, crate::Error> {
+    match value {
+        ValueRef::String(string) => match string.as_str() {
+            Some(string) => Ok(string.to_owned()),
+            None => Err(crate::Error::TypeMismatch {
+                expected: r#type.clone(),
+                found: "invalid utf-8 string",
+            }),
+        },
+        value => Err(mismatch(r#type, value)),
+    }
+}
+
+fn to_array<T>(
+    r#type: &Type,
+    value: &ValueRef,
+    f: impl Fn(&Type, &ValueRef) -> Result<T, crate::Error>,
+) -> Result<Vec<T>, crate::Error> {
+    match value {
+        ValueRef::Array(values) => values.iter().map(|value| f(r#type, value)).collect(),
+        value => Err(mismatch(r#type, value)),
+    }
+}
+
+impl NtValue {
+    /// Converts a msgpack value borrowed from `frame` into the value for a topic of the given type.
+    /// Raw values are sliced out of `frame` instead of being copied.
+    pub(crate) fn from_frame(
+        r#type: &Type,
+        value: &ValueRef,
+        frame: &Bytes,
+    ) -> Result<Self, crate::Error> {
+        Self::from_value_ref(r#type, value, |bytes| frame.slice_ref(bytes))
+    }
+
+    fn from_value_ref(
+        r#type: &Type,
+        value: &ValueRef,
+        to_bytes: impl Fn(&[u8]) -> Bytes,
+    ) -> Result<Self, crate::Error> {
+        Ok(match r#type {
+            Type::Boolean => Self::Boolean(to_bool(r#type, value)?),
+            Type::Double => Self::Double(to_f64(r#type, value)?),
+            Type::Int => Self::Int(to_i64(r#type, value)?),
+            Type::Float => Self::Float(to_f64(r#type, value)? as f32),
+            Type::String | Type::Json => Self::String(to_string(r#type, value)?),
+            Type::Raw
+            | Type::Rpc
+            | Type::MsgPack
+            | Type::ProtoBuf
+            | Type::Struct(_)
+            | Type::StructArray(_)
+            | Type::Proto(_)
+            | Type::Other(_) => match value {
+                ValueRef::Binary(bytes) => Self::Raw(to_bytes(bytes)),
+                value => return Err(mismatch(r#type, value)),
+            },
+            Type::BooleanArray => Self::BooleanArray(to_array(r#type, value, to_bool)?),
+            Type::DoubleArray => Self::DoubleArray(to_array(r#type, value, to_f64)?),
+            Type::IntArray => Self::IntArray(to_array(r#type, value, to_i64)?),
+            Type::FloatArray => Self::FloatArray(
+                to_array(r#type, value, to_f64)?
+                    .into_iter()
+                    .map(|value| value as f32)
+                    .collect(),
+            ),
+            Type::StringArray => Self::StringArray(to_array(r#type, value, to_string)?),
+        })
+    }
+}
+
+/// Converts a decoded msgpack value into the value for a topic of the given type
+impl TryFrom<(&Type, rmpv::Value)> for NtValue {
+    type Error = crate::Error;
+
+    fn try_from((r#type, value): (&Type, rmpv::Value)) -> Result<Self, Self::Error> {
+        Self::from_value_ref(r#type, &value.as_ref(), Bytes::copy_from_slice)
+    }
+}
+
+macro_rules! impl_from {
+    ($($from:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$from> for NtValue {
+                fn from(value: $from) -> Self {
+                    Self::$variant(value.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_from! {
+    bool => Boolean,
+    f64 => Double,
+    i64 => Int,
+    i32 => Int,
+    f32 => Float,
+    String => String,
+    &str => String,
+    Vec<u8> => Raw,
+    Bytes => Raw,
+    Vec<bool> => BooleanArray,
+    Vec<f64> => DoubleArray,
+    Vec<i64> => IntArray,
+    Vec<f32> => FloatArray,
+    Vec<String> => StringArray,
+}
+
+impl From<&[u8]> for NtValue {
+    fn from(value: &[u8]) -> Self {
+        Self::Raw(Bytes::copy_from_slice(value))
+    }
+}
+
+/// Helpers shared by the tests of decoding & encoding values
+#[cfg(test)]
+pub(crate) mod test_util {
+    /// What a [`crate::Error::TypeMismatch`] found, panics for any other result
+    pub(crate) fn found<T: std::fmt::Debug>(result: Result<T, crate::Error>) -> &'static str {
+        match result {
+            Err(crate::Error::TypeMismatch { found, .. }) => found,
+            result => panic!("expected a type mismatch, got {result:?}"),
+        }
+    }
+
+    /// Decodes one msgpack value which has to fill all of `buf`
+    pub(crate) fn read_value(mut buf: &[u8]) -> rmpv::Value {
+        let value = rmpv::decode::read_value(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        test_util::{found, read_value},
+        *,
+    };
+    use rmpv::Value;
+
+    fn convert(r#type: Type, value: Value) -> Result<NtValue, crate::Error> {
+        NtValue::try_from((&r#type, value))
+    }
+
+    #[test]
+    fn any_int_is_a_double() {
+        assert_eq!(
+            convert(Type::Double, Value::from(5u64)).unwrap(),
+            NtValue::Double(5.0)
+        );
+        assert_eq!(
+            convert(Type::Double, Value::from(-3i64)).unwrap(),
+            NtValue::Double(-3.0)
+        );
+        assert_eq!(
+            convert(Type::Double, Value::from(u64::MAX)).unwrap(),
+            NtValue::Double(u64::MAX as f64)
+        );
+        assert_eq!(
+            convert(Type::Float, Value::from(2i64)).unwrap(),
+            NtValue::Float(2.0)
+        );
+        assert_eq!(
+            convert(
+                Type::DoubleArray,
+                Value::from(vec![Value::from(1), Value::from(0.5)])
+            )
+            .unwrap(),
+            NtValue::DoubleArray(vec![1.0, 0.5])
+        );
+    }
+
+    #[test]
+    fn float_is_not_an_int() {
+        assert_eq!(found(convert(Type::Int, Value::from(1.5f64))), "f64");
+        assert_eq!(found(convert(Type::Int, Value::from(u64::MAX))), "integer");
+    }
+
+    #[test]
+    fn invalid_utf8_string() {
+        // fixstr of length 2 with invalid utf-8
+        let value = read_value(&[0xa2, 0xff, 0xfe]);
+        assert_eq!(
+            found(convert(Type::String, value.clone())),
+            "invalid utf-8 string"
+        );
+        assert_eq!(
+            found(convert(Type::StringArray, Value::from(vec![value]))),
+            "invalid utf-8 string"
+        );
+    }
+
+    #[test]
+    fn mismatch_inside_array() {
+        let value = Value::from(vec![Value::from(1.0), Value::from("a")]);
+        assert_eq!(found(convert(Type::DoubleArray, value)), "string");
+
+        let value = Value::from(vec![Value::from(true), Value::from(1)]);
+        assert_eq!(found(convert(Type::BooleanArray, value)), "integer");
+
+        assert_eq!(found(convert(Type::IntArray, Value::from(1))), "integer");
+    }
+
+    #[test]
+    fn raw_types_need_binary() {
+        let r#type = Type::Struct("struct:Pose2d".to_owned());
+        assert_eq!(
+            convert(r#type.clone(), Value::from(vec![1u8, 2])).unwrap(),
+            NtValue::Raw(Bytes::from_static(&[1, 2]))
+        );
+        assert_eq!(found(convert(r#type, Value::from("a"))), "string");
+    }
+}
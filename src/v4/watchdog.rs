@@ -0,0 +1,615 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, watch},
+    task::JoinHandle,
+};
+
+use super::{
+    client::{spawn_cleanup, WeakClient},
+    time_sync::time_diff,
+    Client, MessageData, PublishedTopic, Subscription, SubscriptionOptions, Type,
+};
+
+/// Change in whether a watched topic is updating as often as expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    /// The topic wasn't updated for longer than its expected period
+    Stale {
+        topic: String,
+        /// Server timestamp of the last value, `None` if no value was received since the watchdog started
+        last_update: Option<u32>,
+        expected_period: Duration,
+    },
+    /// A stale topic was updated again
+    Recovered { topic: String, stale_for: Duration },
+}
+
+/// Which watched topics are stale, also published as json to the health topic
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct HealthSummary {
+    pub stale: Vec<String>,
+    pub healthy: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    prefix: bool,
+    period: Duration,
+}
+
+#[derive(Debug)]
+struct TopicState {
+    period: Duration,
+    /// Server time the topic started being watched
+    since: u32,
+    last_update: Option<u32>,
+    stale: bool,
+}
+
+/// Watches topics for values stopping, using the server timestamps of the values.
+///
+/// Events are received through the [`WatchdogHandle`] returned by [`Watchdog::start`].
+#[derive(Debug)]
+pub struct Watchdog {
+    client: Client,
+    rules: Vec<Rule>,
+    check_interval: Duration,
+    health_topic: Option<String>,
+}
+
+impl Watchdog {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            rules: Vec::new(),
+            check_interval: Duration::from_millis(50),
+            health_topic: None,
+        }
+    }
+
+    /// Expects the topic to be updated at least once per `period`
+    pub fn watch_topic(mut self, name: impl Into<String>, period: Duration) -> Self {
+        self.rules.push(Rule {
+            name: name.into(),
+            prefix: false,
+            period,
+        });
+        self
+    }
+
+    /// Expects every announced topic starting with `prefix` to be updated at least once per `period`.
+    /// The longest matching prefix is used if several match, [`Self::watch_topic`] takes precedence.
+    pub fn watch_prefix(mut self, prefix: impl Into<String>, period: Duration) -> Self {
+        self.rules.push(Rule {
+            name: prefix.into(),
+            prefix: true,
+            period,
+        });
+        self
+    }
+
+    /// How often topics are checked, 50ms by default.
+    /// Intervals shorter than a millisecond are rounded up to one.
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Publishes the [`HealthSummary`] as json to this topic whenever it changes
+    pub fn with_health_topic(mut self, name: impl Into<String>) -> Self {
+        self.health_topic = Some(name.into());
+        self
+    }
+
+    /// Subscribes to the watched topics & starts checking them in a task,
+    /// which stops when the handle or every clone of the client is dropped
+    pub async fn start(self) -> Result<WatchdogHandle, crate::Error> {
+        let mut subscriptions = Vec::with_capacity(2);
+        let names: Vec<&str> = self
+            .rules
+            .iter()
+            .filter(|rule| !rule.prefix)
+            .map(|rule| rule.name.as_str())
+            .collect();
+        if !names.is_empty() {
+            subscriptions.push(self.client.subscribe(&names).await?);
+        }
+
+        let prefixes: Vec<&str> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.prefix)
+            .map(|rule| rule.name.as_str())
+            .collect();
+        if !prefixes.is_empty() {
+            let options = SubscriptionOptions {
+                prefix: Some(true),
+                ..Default::default()
+            };
+            subscriptions.push(
+                self.client
+                    .subscribe_w_options(&prefixes, Some(options))
+                    .await?,
+            );
+        }
+
+        let health_topic = match &self.health_topic {
+            Some(name) => Some(self.client.publish_topic(name, Type::Json, None).await?),
+            None => None,
+        };
+
+        let subuids = subscriptions
+            .iter()
+            .map(|subscription| subscription.data.subuid)
+            .collect();
+        let (events, _) = broadcast::channel(64);
+        let (health, health_receiver) = watch::channel(HealthSummary::default());
+        let task = tokio::spawn(
+            WatchdogTask::new(
+                &self.client,
+                self.rules,
+                events.clone(),
+                health,
+                health_topic.clone(),
+            )
+            .run(subscriptions, self.check_interval),
+        );
+
+        Ok(WatchdogHandle {
+            client: self.client.downgrade(),
+            events,
+            health: health_receiver,
+            task,
+            subuids,
+            health_topic,
+        })
+    }
+}
+
+/// Running [`Watchdog`], dropping it stops watching
+#[derive(Debug)]
+pub struct WatchdogHandle {
+    client: WeakClient,
+    events: broadcast::Sender<WatchdogEvent>,
+    health: watch::Receiver<HealthSummary>,
+    task: JoinHandle<()>,
+    /// Subscriptions of the task, unsubscribed when dropped
+    subuids: Vec<i32>,
+    health_topic: Option<PublishedTopic>,
+}
+
+impl WatchdogHandle {
+    /// Receives every [`WatchdogEvent`] after this is called
+    pub fn events(&self) -> broadcast::Receiver<WatchdogEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn health(&self) -> HealthSummary {
+        self.health.borrow().clone()
+    }
+
+    /// Receives every change of the [`HealthSummary`]
+    pub fn watch_health(&self) -> watch::Receiver<HealthSummary> {
+        self.health.clone()
+    }
+}
+
+impl Drop for WatchdogHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        // Nothing to clean up on the server if the client is gone
+        let client = match self.client.upgrade() {
+            Some(client) => client,
+            None => return,
+        };
+        let subuids = std::mem::take(&mut self.subuids);
+        let health_topic = self.health_topic.take();
+        spawn_cleanup(async move {
+            for subuid in subuids {
+                client.unsubscribe_uid(subuid).await.ok();
+            }
+            if let Some(topic) = health_topic {
+                client.unpublish(topic).await.ok();
+            }
+        });
+    }
+}
+
+struct WatchdogTask {
+    // Weak so the watchdog doesn't keep the connection open
+    client: WeakClient,
+    rules: Vec<Rule>,
+    /// Server time the watchdog started, used for topics which haven't sent a value
+    since: u32,
+    topics: BTreeMap<String, TopicState>,
+    events: broadcast::Sender<WatchdogEvent>,
+    health: watch::Sender<HealthSummary>,
+    health_topic: Option<PublishedTopic>,
+    /// A topic was added or became stale or healthy since the health summary was updated
+    health_changed: bool,
+}
+
+impl WatchdogTask {
+    fn new(
+        client: &Client,
+        rules: Vec<Rule>,
+        events: broadcast::Sender<WatchdogEvent>,
+        health: watch::Sender<HealthSummary>,
+        health_topic: Option<PublishedTopic>,
+    ) -> Self {
+        Self {
+            since: client.server_time(),
+            client: client.downgrade(),
+            rules,
+            topics: BTreeMap::new(),
+            events,
+            health,
+            health_topic,
+            health_changed: false,
+        }
+    }
+
+    async fn run(mut self, subscriptions: Vec<Subscription>, check_interval: Duration) {
+        let mut values = stream::select_all(subscriptions);
+        let mut interval = tokio::time::interval(check_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let message = tokio::select! {
+                message = values.next() => match message {
+                    Some(message) => Some(message),
+                    // Client was dropped, which ends its subscriptions
+                    None => break,
+                },
+                _ = interval.tick() => None,
+            };
+
+            let client = match self.client.upgrade() {
+                Some(client) => client,
+                None => break,
+            };
+            match message {
+                Some(message) => self.on_value(&client, &message),
+                None => self.check(&client),
+            }
+
+            if self.health_changed {
+                self.update_health(&client).await;
+            }
+        }
+    }
+
+    /// Expected period of a topic, `None` if it isn't watched
+    fn period(&self, name: &str) -> Option<Duration> {
+        self.rules
+            .iter()
+            .find(|rule| !rule.prefix && rule.name == name)
+            .or_else(|| {
+                self.rules
+                    .iter()
+                    .filter(|rule| rule.prefix && name.starts_with(&rule.name))
+                    .max_by_key(|rule| rule.name.len())
+            })
+            .map(|rule| rule.period)
+    }
+
+    fn state(&mut self, name: &str) -> Option<&mut TopicState> {
+        if !self.topics.contains_key(name) {
+            let period = self.period(name)?;
+            self.health_changed = true;
+            self.topics.insert(
+                name.to_owned(),
+                TopicState {
+                    period,
+                    since: self.since,
+                    last_update: None,
+                    stale: false,
+                },
+            );
+        }
+
+        self.topics.get_mut(name)
+    }
+
+    fn on_value(&mut self, client: &Client, message: &Arc<MessageData>) {
+        let now = client.server_time();
+        let state = match self.state(&message.topic_name) {
+            Some(state) => state,
+            None => return,
+        };
+
+        // Values sent again, such as the retained value the server sends on reconnect, aren't updates
+        let last = state.last_update.unwrap_or(state.since);
+        if time_diff(message.timestamp, last) <= 0 {
+            return;
+        }
+        state.last_update = Some(message.timestamp);
+
+        let age = time_diff(now, message.timestamp);
+        if state.stale && age <= state.period.as_micros() as i64 {
+            state.stale = false;
+            let stale_for = Duration::from_micros(time_diff(now, last).max(0) as u64)
+                .saturating_sub(state.period);
+            self.health_changed = true;
+            self.events
+                .send(WatchdogEvent::Recovered {
+                    topic: message.topic_name.clone(),
+                    stale_for,
+                })
+                .ok();
+        }
+    }
+
+    fn check(&mut self, client: &Client) {
+        // Topics under a prefix are watched from when they are announced
+        let announced: Vec<String> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.prefix)
+            .flat_map(|rule| client.topics(&rule.name))
+            .map(|topic| topic.name)
+            .collect();
+        for name in announced {
+            if !self.topics.contains_key(&name) {
+                let since = client.server_time();
+                if let Some(state) = self.state(&name) {
+                    state.since = since;
+                }
+            }
+        }
+        let exact: Vec<String> = self
+            .rules
+            .iter()
+            .filter(|rule| !rule.prefix)
+            .map(|rule| rule.name.clone())
+            .collect();
+        for name in exact {
+            self.state(&name);
+        }
+
+        let now = client.server_time();
+        for (name, state) in &mut self.topics {
+            let last = state.last_update.unwrap_or(state.since);
+            let age = time_diff(now, last);
+            if !state.stale && age > state.period.as_micros() as i64 {
+                state.stale = true;
+                self.health_changed = true;
+                cfg_tracing! {
+                    tracing::warn!("Topic {name} is stale, last updated {age}us ago");
+                }
+                self.events
+                    .send(WatchdogEvent::Stale {
+                        topic: name.clone(),
+                        last_update: state.last_update,
+                        expected_period: state.period,
+                    })
+                    .ok();
+            }
+        }
+    }
+
+    async fn update_health(&mut self, client: &Client) {
+        self.health_changed = false;
+        let mut summary = HealthSummary::default();
+        for (name, state) in &self.topics {
+            if state.stale {
+                summary.stale.push(name.clone());
+            } else {
+                summary.healthy.push(name.clone());
+            }
+        }
+
+        if let Some(topic) = &self.health_topic {
+            if let Ok(json) = serde_json::to_string(&summary) {
+                crate::log_result(client.publish_value(topic, json).await).ok();
+            }
+        }
+        self.health.send_replace(summary);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v4::{Config, ManualClock, NtValue};
+
+    const PERIOD: Duration = Duration::from_millis(100);
+
+    fn client(clock: &ManualClock) -> Client {
+        let config = Config {
+            clock: Arc::new(clock.clone()),
+            ..Default::default()
+        };
+        Client::unconnected_w_config(config).0
+    }
+
+    fn task(
+        watchdog: Watchdog,
+    ) -> (
+        WatchdogTask,
+        broadcast::Receiver<WatchdogEvent>,
+        watch::Receiver<HealthSummary>,
+    ) {
+        let (events, events_receiver) = broadcast::channel(64);
+        let (health, health_receiver) = watch::channel(HealthSummary::default());
+        let task = WatchdogTask::new(&watchdog.client, watchdog.rules, events, health, None);
+        (task, events_receiver, health_receiver)
+    }
+
+    fn value(topic_name: &str, timestamp: u32) -> Arc<MessageData> {
+        MessageData::received(topic_name, timestamp, Type::Double, NtValue::Double(0.0))
+    }
+
+    #[test]
+    fn rule_precedence() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        let watchdog = Watchdog::new(client)
+            .watch_prefix("/a/", Duration::from_millis(1))
+            .watch_prefix("/a/b/", Duration::from_millis(2))
+            .watch_topic("/a/b/c", Duration::from_millis(3))
+            .watch_prefix("/a/b/c", Duration::from_millis(4));
+        let (task, ..) = task(watchdog);
+
+        // Exact rule beats any prefix, even a longer one declared later
+        assert_eq!(task.period("/a/b/c"), Some(Duration::from_millis(3)));
+        // Longest prefix wins
+        assert_eq!(task.period("/a/b/c/d"), Some(Duration::from_millis(4)));
+        assert_eq!(task.period("/a/b/x"), Some(Duration::from_millis(2)));
+        assert_eq!(task.period("/a/x"), Some(Duration::from_millis(1)));
+        assert_eq!(task.period("/b"), None);
+    }
+
+    #[test]
+    fn stale_after_period() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        let (mut task, mut events, _) =
+            task(Watchdog::new(client.clone()).watch_topic("/a", PERIOD));
+
+        task.check(&client);
+        clock.advance(PERIOD);
+        task.check(&client);
+        assert!(events.try_recv().is_err());
+
+        clock.advance(Duration::from_millis(1));
+        task.check(&client);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            WatchdogEvent::Stale {
+                topic: "/a".to_owned(),
+                last_update: None,
+                expected_period: PERIOD,
+            }
+        );
+        // Only reported once
+        task.check(&client);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn recovers_only_on_newer_value() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        let (mut task, mut events, _) =
+            task(Watchdog::new(client.clone()).watch_topic("/a", PERIOD));
+
+        clock.advance(Duration::from_millis(10));
+        let first = client.server_time();
+        task.on_value(&client, &value("/a", first));
+        clock.advance(PERIOD * 2);
+        task.check(&client);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(WatchdogEvent::Stale {
+                last_update: Some(last_update),
+                ..
+            }) if last_update == first
+        ));
+
+        // The retained value sent again after a reconnect
+        task.on_value(&client, &value("/a", first));
+        task.on_value(&client, &value("/a", first - 1));
+        assert!(events.try_recv().is_err());
+        assert_eq!(task.topics["/a"].last_update, Some(first));
+        assert!(task.topics["/a"].stale);
+
+        // Newer, but still older than the period
+        let newer = first + 1000;
+        task.on_value(&client, &value("/a", newer));
+        assert!(events.try_recv().is_err());
+        assert_eq!(task.topics["/a"].last_update, Some(newer));
+
+        task.on_value(&client, &value("/a", client.server_time()));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(WatchdogEvent::Recovered { topic, .. }) if topic == "/a"
+        ));
+        task.check(&client);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn health_summary() {
+        let clock = ManualClock::new();
+        let client = client(&clock);
+        let watchdog = Watchdog::new(client.clone())
+            .watch_topic("/a", PERIOD)
+            .watch_prefix("/b/", PERIOD);
+        let (mut task, _, health) = task(watchdog);
+
+        task.check(&client);
+        task.on_value(&client, &value("/b/c", client.server_time() + 1));
+        task.on_value(&client, &value("/other", client.server_time() + 1));
+        assert!(task.health_changed);
+        task.update_health(&client).await;
+        assert_eq!(
+            *health.borrow(),
+            HealthSummary {
+                stale: vec![],
+                healthy: vec!["/a".to_owned(), "/b/c".to_owned()],
+            }
+        );
+
+        clock.advance(PERIOD / 2);
+        task.on_value(&client, &value("/b/c", client.server_time()));
+        clock.advance(PERIOD);
+        task.check(&client);
+        task.update_health(&client).await;
+        assert_eq!(
+            *health.borrow(),
+            HealthSummary {
+                stale: vec!["/a".to_owned()],
+                healthy: vec!["/b/c".to_owned()],
+            }
+        );
+
+        task.check(&client);
+        assert!(!task.health_changed);
+    }
+
+    #[test]
+    fn zero_check_interval() {
+        let clock = ManualClock::new();
+        let watchdog = Watchdog::new(client(&clock)).with_check_interval(Duration::ZERO);
+        assert_eq!(watchdog.check_interval, Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn drop_cleans_up_on_server() {
+        use tokio_tungstenite::tungstenite::Message;
+
+        let (client, mut sent) = Client::unconnected();
+        let handle = Watchdog::new(client.clone())
+            .watch_topic("/a", PERIOD)
+            .watch_prefix("/b/", PERIOD)
+            .with_health_topic("/health")
+            .start()
+            .await
+            .unwrap();
+        let mut methods = Vec::new();
+        let mut receive = |methods: &mut Vec<String>| {
+            while let Ok(Message::Text(text)) = sent.try_recv() {
+                let messages: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap();
+                methods.extend(
+                    messages
+                        .iter()
+                        .filter_map(|message| message["method"].as_str().map(str::to_owned)),
+                );
+            }
+        };
+        receive(&mut methods);
+        assert_eq!(methods, ["subscribe", "subscribe", "publish"]);
+
+        methods.clear();
+        drop(handle);
+        tokio::task::yield_now().await;
+        receive(&mut methods);
+        assert_eq!(methods, ["unsubscribe", "unsubscribe", "unpublish"]);
+    }
+}